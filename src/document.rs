@@ -0,0 +1,3527 @@
+use crate::highlighting;
+use crate::CursorModel;
+use crate::IndentKind;
+use crate::Match;
+use crate::Position;
+use crate::Row;
+use crate::SearchDirection;
+use crate::SearchOptions;
+use crate::SearchResult;
+use crate::Selection;
+use crate::Span;
+use crate::Syntax;
+use crate::SyntaxContext;
+use std::cell::Cell;
+use std::cmp;
+use std::fmt;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use unicode_normalization::{is_nfc, UnicodeNormalization};
+use unicode_segmentation::UnicodeSegmentation;
+
+const BRACKETS: [(&str, &str); 3] = [("(", ")"), ("[", "]"), ("{", "}")];
+/// How many rows at each end of a file are checked for a modeline, as Vim
+/// does by default.
+const MODELINE_ROWS: usize = 5;
+/// Indent width `detect_indent` falls back to.
+const DEFAULT_INDENT_WIDTH: usize = 4;
+/// Deeper steps are taken as alignment rather than indentation.
+const MAX_INDENT_STEP: usize = 8;
+
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LineEnding {
+    #[default]
+    Lf,
+    CrLf,
+}
+
+impl LineEnding {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LineEnding::Lf => "\n",
+            LineEnding::CrLf => "\r\n",
+        }
+    }
+}
+
+/// Optional processing applied to each line when opening a file.
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OpenOptions {
+    /// Normalize every line to NFC, so precomposed and decomposed forms of
+    /// the same text compare equal. Off by default to keep byte-exact round
+    /// trips.
+    pub normalize: bool,
+    /// Remove ANSI CSI escape sequences such as `\x1b[31m`, e.g. to read
+    /// captured terminal output. Off by default so files that legitimately
+    /// contain escapes are kept intact.
+    pub strip_ansi: bool,
+}
+
+/// Clean-up applied to text inserted with `Document::insert_str`, e.g. when
+/// pasting.
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PasteOptions {
+    /// Drop trailing whitespace from each pasted line that ends a row.
+    pub trim_trailing_whitespace: bool,
+    /// Replace the common indentation of the lines after the first with the
+    /// indentation of the row pasted into.
+    pub reindent: bool,
+}
+
+pub struct Document {
+    rows: Vec<Row>,
+    filename: Option<String>,
+    /// The file the buffer was last read from or written to, which a clean
+    /// `save` to the same file can skip.
+    saved_filename: Option<String>,
+    dirty: bool,
+    /// Whether the buffer changed since the swap file was last written.
+    swap_stale: bool,
+    syntax: Syntax,
+    line_ending: LineEnding,
+    ensure_final_newline: bool,
+    continue_comments: bool,
+    hide_active_line_matches: bool,
+    dim_unmatched: bool,
+    virtual_space: bool,
+    word_chars: String,
+    /// Positions currently marked as `MatchBracket`, with the highlighting
+    /// they had before, so it can be restored.
+    bracket_highlight: Vec<(Position, highlighting::Type)>,
+    active_line: Option<usize>,
+    normalized: bool,
+    /// Cached `max_line_width` as `(tab_width, width)`, cleared on edits.
+    max_line_width: Cell<Option<(usize, usize)>>,
+    /// Each entry is one undo step: the edits it is made of, oldest first.
+    undo_stack: Vec<Vec<Edit>>,
+    transaction_depth: usize,
+    max_undo: usize,
+    cursor_model: CursorModel,
+}
+
+/// One recorded change: the rows starting at `at.y` that were replaced by
+/// `inserted` new rows, where `at` is the position the edit was made at.
+struct Edit {
+    at: Position,
+    removed: Vec<String>,
+    inserted: usize,
+}
+
+impl Default for Document {
+    fn default() -> Self {
+        Self {
+            rows: Vec::new(),
+            filename: None,
+            saved_filename: None,
+            dirty: false,
+            swap_stale: false,
+            syntax: Syntax::default(),
+            line_ending: LineEnding::default(),
+            ensure_final_newline: true,
+            continue_comments: false,
+            hide_active_line_matches: false,
+            dim_unmatched: false,
+            virtual_space: false,
+            word_chars: String::from("_"),
+            bracket_highlight: Vec::new(),
+            active_line: None,
+            normalized: false,
+            max_line_width: Cell::new(None),
+            undo_stack: Vec::new(),
+            transaction_depth: 0,
+            max_undo: 1000,
+            cursor_model: CursorModel::default(),
+        }
+    }
+}
+
+impl Document {
+    pub fn open(filename: &str) -> Result<Self, io::Error> {
+        Self::open_with(filename, OpenOptions::default())
+    }
+
+    /// Opens a location such as `main.rs:42:10`, as printed by compilers
+    /// and grep. The trailing 1-based `:line` and `:col` are optional, and
+    /// the returned position is clamped to the document. A spec without a
+    /// numeric suffix is opened as a literal filename.
+    pub fn open_at(spec: &str) -> Result<(Self, Position), io::Error> {
+        let mut filename = spec;
+        let mut numbers = Vec::new();
+        while numbers.len() < 2 {
+            let Some((rest, number)) = filename.rsplit_once(':') else {
+                break;
+            };
+            if number.is_empty() || !number.bytes().all(|b| b.is_ascii_digit()) {
+                break;
+            }
+            let Ok(number) = number.parse::<usize>() else {
+                break;
+            };
+            numbers.push(number);
+            filename = rest;
+        }
+        numbers.reverse();
+        let document = Self::open(filename)?;
+        let y = numbers
+            .first()
+            .map_or(0, |line| line.saturating_sub(1))
+            .min(document.len().saturating_sub(1));
+        let x = numbers
+            .get(1)
+            .map_or(0, |col| col.saturating_sub(1))
+            .min(document.row(y).map_or(0, Row::len));
+        Ok((document, Position { x, y }))
+    }
+
+    pub fn open_with(filename: &str, options: OpenOptions) -> Result<Self, io::Error> {
+        let file = fs::File::open(filename)?;
+        let mut document = Self::parse(
+            BufReader::new(file),
+            Syntax::from_filename(filename),
+            options,
+        )?;
+        document.filename = Some(filename.to_string());
+        document.saved_filename = Some(filename.to_string());
+        let rows = &document.rows;
+        let modeline = rows
+            .iter()
+            .take(MODELINE_ROWS)
+            .chain(rows.iter().skip(MODELINE_ROWS).rev().take(MODELINE_ROWS))
+            .find_map(|row| Syntax::for_modeline(row.as_str()));
+        if let Some(syntax) = modeline {
+            document.syntax = syntax;
+            document.highlight(None, SearchOptions::default());
+        } else if document.syntax == Syntax::default() {
+            let shebang = document
+                .rows
+                .first()
+                .and_then(|row| Syntax::for_content(row.as_str()));
+            if let Some(syntax) = shebang {
+                document.syntax = syntax;
+                document.highlight(None, SearchOptions::default());
+            }
+        }
+        Ok(document)
+    }
+
+    /// Builds a document from any buffered reader, such as stdin or an
+    /// in-memory buffer. The document has no filename.
+    pub fn read_from<R: BufRead>(reader: R) -> Result<Self, io::Error> {
+        Self::parse(reader, Syntax::default(), OpenOptions::default())
+    }
+
+    /// Builds an unnamed, clean document from `text`, e.g. for an untitled
+    /// buffer. Lines are split the same way `open` splits a file.
+    // Infallible, so `FromStr` and its `Result` would only get in the way.
+    #[allow(clippy::should_implement_trait)]
+    pub fn from_str(text: &str) -> Self {
+        Self::parse(text.as_bytes(), Syntax::default(), OpenOptions::default())
+            .expect("reading from a string cannot fail")
+    }
+
+    fn parse<R: BufRead>(
+        mut reader: R,
+        syntax: Syntax,
+        options: OpenOptions,
+    ) -> Result<Self, io::Error> {
+        let mut rows = Vec::new();
+        let mut line_ending = LineEnding::Lf;
+        let mut normalized = false;
+        let mut line = String::new();
+        while reader.read_line(&mut line)? > 0 {
+            if line.ends_with('\n') {
+                line.pop();
+                if line.ends_with('\r') {
+                    line.pop();
+                    line_ending = LineEnding::CrLf;
+                }
+            }
+            if options.strip_ansi && line.contains('\x1b') {
+                line = strip_ansi(&line);
+            }
+            if options.normalize && !is_nfc(&line) {
+                line = line.nfc().collect();
+                normalized = true;
+            }
+            let mut row = Row::from(&line[..]);
+            row.highlight(&syntax, None, SearchOptions::default());
+            rows.push(row);
+            line.clear();
+        }
+        Ok(Self {
+            rows,
+            syntax,
+            line_ending,
+            normalized,
+            ..Self::default()
+        })
+    }
+
+    /// Returns whether a swap file left behind by an earlier session exists
+    /// for `filename`.
+    pub fn has_swap(filename: &str) -> bool {
+        swap_path(Path::new(filename)).exists()
+    }
+
+    /// Rebuilds a document from the swap file for `filename`. The recovered
+    /// buffer is dirty, and the swap file stays in place until it is saved.
+    pub fn recover_from_swap(filename: &str) -> Result<Self, io::Error> {
+        let contents = fs::read_to_string(swap_path(Path::new(filename)))?;
+        let mut lines = contents.lines();
+        let original = lines
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "empty swap file"))?;
+        let line_ending = match lines.next() {
+            Some("lf") => LineEnding::Lf,
+            Some("crlf") => LineEnding::CrLf,
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "swap file has no line ending",
+                ))
+            }
+        };
+        let syntax = Syntax::from_filename(original);
+        Ok(Self {
+            rows: highlighted_rows(lines, &syntax),
+            filename: Some(original.to_string()),
+            dirty: true,
+            line_ending,
+            syntax,
+            ..Self::default()
+        })
+    }
+
+    pub fn filename(&self) -> Option<&str> {
+        self.filename.as_deref()
+    }
+
+    /// Changes the file the document saves to, e.g. for "Save As", without
+    /// writing the document. Unsaved changes keep their swap file, which
+    /// moves next to the new name.
+    pub fn set_filename(&mut self, filename: Option<String>) {
+        // Best effort, as in `mark_dirty`.
+        let _ = self.remove_swap();
+        self.filename = filename;
+        if self.dirty {
+            let _ = self.write_swap();
+        }
+    }
+
+    pub fn syntax(&self) -> &Syntax {
+        &self.syntax
+    }
+
+    /// Switches to `syntax` and re-highlights the whole document.
+    pub fn set_syntax(&mut self, syntax: Syntax) {
+        self.syntax = syntax;
+        self.highlight(None, SearchOptions::default());
+    }
+
+    pub fn row(&self, index: usize) -> Option<&Row> {
+        self.rows.get(index)
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, Row> {
+        self.rows.iter()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.rows.len()
+    }
+
+    /// Counts the blank rows at the start of the document. With
+    /// `whitespace_is_blank`, rows holding only whitespace count as blank.
+    pub fn leading_blank_lines(&self, whitespace_is_blank: bool) -> usize {
+        self.rows
+            .iter()
+            .take_while(|row| is_blank(row, whitespace_is_blank))
+            .count()
+    }
+
+    /// Counts the blank rows at the end of the document. With
+    /// `whitespace_is_blank`, rows holding only whitespace count as blank.
+    pub fn trailing_blank_lines(&self, whitespace_is_blank: bool) -> usize {
+        self.rows
+            .iter()
+            .rev()
+            .take_while(|row| is_blank(row, whitespace_is_blank))
+            .count()
+    }
+
+    /// Returns whether opening with `OpenOptions::normalize` changed any
+    /// line.
+    pub fn was_normalized(&self) -> bool {
+        self.normalized
+    }
+
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    /// Marks the buffer clean or dirty, for callers that load or save it
+    /// through their own path. Marking it clean deletes the swap file, which
+    /// would otherwise offer a stale recovery on the next open.
+    pub fn set_dirty(&mut self, dirty: bool) {
+        if self.dirty && !dirty {
+            // Best effort, as in `mark_dirty`.
+            let _ = self.remove_swap();
+        }
+        self.dirty = dirty;
+    }
+
+    pub fn line_ending(&self) -> LineEnding {
+        self.line_ending
+    }
+
+    pub fn set_line_ending(&mut self, line_ending: LineEnding) {
+        self.line_ending = line_ending;
+    }
+
+    pub fn ensure_final_newline(&self) -> bool {
+        self.ensure_final_newline
+    }
+
+    /// Sets whether the last row is terminated by a line ending on save.
+    pub fn set_ensure_final_newline(&mut self, ensure_final_newline: bool) {
+        self.ensure_final_newline = ensure_final_newline;
+    }
+
+    pub fn cursor_model(&self) -> CursorModel {
+        self.cursor_model
+    }
+
+    /// Sets whether the columns passed to and returned by `insert`,
+    /// `insert_newline`, `delete`, `backspace`, `find` and `find_match`
+    /// count graphemes or codepoints. Everything else always counts
+    /// graphemes.
+    pub fn set_cursor_model(&mut self, cursor_model: CursorModel) {
+        self.cursor_model = cursor_model;
+    }
+
+    /// Converts `at.x` from `model` units to a grapheme index.
+    fn to_graphemes(&self, at: &Position, model: CursorModel) -> Position {
+        Position {
+            x: self
+                .rows
+                .get(at.y)
+                .map_or(at.x, |row| row.from_model_index(at.x, model)),
+            y: at.y,
+        }
+    }
+
+    /// Converts `at` into an LSP-style `(line, column)` where the column
+    /// counts UTF-16 code units, so characters outside the Basic
+    /// Multilingual Plane, like most emoji, count as two.
+    pub fn to_utf16_position(&self, at: &Position) -> (usize, usize) {
+        let column = self.rows.get(at.y).map_or(0, |row| {
+            row.graphemes()
+                .take(at.x)
+                .map(|grapheme| grapheme.encode_utf16().count())
+                .sum()
+        });
+        (at.y, column)
+    }
+
+    /// Converts an LSP-style UTF-16 column on `line` back into a position.
+    /// A column inside a grapheme maps to that grapheme.
+    pub fn from_utf16_position(&self, line: usize, utf16_col: usize) -> Position {
+        let Some(row) = self.rows.get(line) else {
+            return Position { x: 0, y: line };
+        };
+        let mut column = 0;
+        for (x, grapheme) in row.grapheme_indices() {
+            column += grapheme.encode_utf16().count();
+            if column > utf16_col {
+                return Position { x, y: line };
+            }
+        }
+        Position {
+            x: row.len(),
+            y: line,
+        }
+    }
+
+    /// Converts `at` into a line and byte column, as used by ctags and
+    /// grep.
+    pub fn to_byte_position(&self, at: &Position) -> (usize, usize) {
+        let column = self
+            .rows
+            .get(at.y)
+            .map_or(0, |row| row.grapheme_to_byte(at.x));
+        (at.y, column)
+    }
+
+    /// Converts a byte column on `line` back into a position. A byte inside
+    /// a grapheme maps to that grapheme.
+    pub fn from_byte_position(&self, line: usize, byte: usize) -> Position {
+        let x = self
+            .rows
+            .get(line)
+            .map_or(0, |row| row.byte_to_grapheme(byte));
+        Position { x, y: line }
+    }
+
+    pub fn word_chars(&self) -> &str {
+        &self.word_chars
+    }
+
+    /// Sets which characters besides alphanumerics count as part of a word
+    /// for `word_at`, `next_word_boundary` and `find_whole_word`, e.g. `"_"`
+    /// for code (the default), `""` for prose or `"_$"` for shell.
+    pub fn set_word_chars(&mut self, word_chars: &str) {
+        self.word_chars = word_chars.to_string();
+    }
+
+    /// Returns the word containing `at`, or `None` if `at` is not on a word.
+    pub fn word_at(&self, at: &Position) -> Option<Match> {
+        let (start, end) = self.rows.get(at.y)?.word_at(at.x, &self.word_chars)?;
+        Some(Match {
+            start: Position { x: start, y: at.y },
+            len: end - start,
+        })
+    }
+
+    /// Returns the start of the next word after `at`, looking on following
+    /// rows if needed, or the end of the document if no word follows.
+    pub fn next_word_boundary(&self, at: &Position) -> Position {
+        for (y, row) in self.rows.iter().enumerate().skip(at.y) {
+            if y > at.y && row.word_at(0, &self.word_chars).is_some() {
+                return Position { x: 0, y };
+            }
+            let x = if y == at.y { at.x } else { 0 };
+            let next = row.next_word_boundary(x, &self.word_chars);
+            if next < row.len() {
+                return Position { x: next, y };
+            }
+        }
+        let y = self.rows.len().saturating_sub(1);
+        Position {
+            x: self.rows.get(y).map_or(0, Row::len),
+            y,
+        }
+    }
+
+    /// Like `find_match`, but skips matches that are part of a longer word.
+    pub fn find_whole_word(
+        &self,
+        query: &str,
+        at: &Position,
+        direction: SearchDirection,
+        options: SearchOptions,
+    ) -> Option<Match> {
+        let mut at = *at;
+        loop {
+            let found = self.find_grapheme_match(query, &at, direction, options)?;
+            let row = &self.rows[found.start.y];
+            if row.is_whole_word(found.start.x, found.start.x + found.len, &self.word_chars) {
+                return Some(found);
+            }
+            at = match direction {
+                SearchDirection::Forward => Position {
+                    x: found.start.x + 1,
+                    y: found.start.y,
+                },
+                SearchDirection::Backward if found.start.x > 0 => Position {
+                    x: found.start.x - 1,
+                    y: found.start.y,
+                },
+                SearchDirection::Backward => Position {
+                    x: self.rows.get(found.start.y.checked_sub(1)?)?.len(),
+                    y: found.start.y - 1,
+                },
+            };
+        }
+    }
+
+    pub fn hide_active_line_matches(&self) -> bool {
+        self.hide_active_line_matches
+    }
+
+    /// Sets whether `highlight` leaves search matches on the active line
+    /// unmarked, to keep the line being edited free of noise. Takes effect
+    /// the next time the rows are highlighted.
+    pub fn set_hide_active_line_matches(&mut self, hide: bool) {
+        self.hide_active_line_matches = hide;
+    }
+
+    pub fn dim_unmatched(&self) -> bool {
+        self.dim_unmatched
+    }
+
+    /// Sets whether `highlight`, given a search word, marks every row that
+    /// does not contain it as `Type::Dimmed`, e.g. to skim a log for the
+    /// lines of interest. Rows with a match are highlighted as usual. Takes
+    /// effect the next time the rows are highlighted.
+    pub fn set_dim_unmatched(&mut self, dim: bool) {
+        self.dim_unmatched = dim;
+    }
+
+    pub fn virtual_space(&self) -> bool {
+        self.virtual_space
+    }
+
+    /// Sets whether the cursor may sit past the end of a row, as in
+    /// alignment-heavy editing. Spaces up to the cursor only appear once
+    /// `type_char` inserts something there.
+    pub fn set_virtual_space(&mut self, virtual_space: bool) {
+        self.virtual_space = virtual_space;
+    }
+
+    pub fn continue_comments(&self) -> bool {
+        self.continue_comments
+    }
+
+    /// Sets whether breaking a line inside a line comment starts the new
+    /// line with the same indentation and comment marker.
+    pub fn set_continue_comments(&mut self, continue_comments: bool) {
+        self.continue_comments = continue_comments;
+    }
+
+    /// Returns the width in display columns of the widest row. The result
+    /// is cached until the next edit.
+    pub fn max_line_width(&self, tab_width: usize) -> usize {
+        if let Some((cached_tab_width, width)) = self.max_line_width.get() {
+            if cached_tab_width == tab_width {
+                return width;
+            }
+        }
+        let width = self
+            .rows
+            .iter()
+            .map(|row| row.width(tab_width))
+            .max()
+            .unwrap_or(0);
+        self.max_line_width.set(Some((tab_width, width)));
+        width
+    }
+
+    /// Guesses the file's indent unit from its leading whitespace: tabs, or
+    /// the most common step by which space indentation deepens, e.g. `2`.
+    /// Tab-indented files report `(Tabs, 4)`, and files with nothing to go
+    /// on `(Spaces, 4)`.
+    pub fn detect_indent(&self) -> (IndentKind, usize) {
+        let mut tabs = 0;
+        let mut spaces = 0;
+        let mut steps = [0_usize; MAX_INDENT_STEP + 1];
+        let mut previous = 0;
+        for row in self.rows.iter().filter(|row| !is_blank(row, true)) {
+            let (indent, kind) = row.leading_indent();
+            match kind {
+                IndentKind::Tabs => tabs += 1,
+                IndentKind::Spaces => spaces += 1,
+                IndentKind::None | IndentKind::Mixed => {}
+            }
+            if matches!(kind, IndentKind::Spaces | IndentKind::None) {
+                if indent > previous && indent - previous <= MAX_INDENT_STEP {
+                    steps[indent - previous] += 1;
+                }
+                previous = indent;
+            }
+        }
+        if tabs > spaces {
+            return (IndentKind::Tabs, DEFAULT_INDENT_WIDTH);
+        }
+        // The smallest step wins a tie, e.g. 2 over 4 when blocks nest.
+        let width = (1..=MAX_INDENT_STEP)
+            .rev()
+            .max_by_key(|&step| steps[step])
+            .filter(|&step| steps[step] > 0)
+            .unwrap_or(DEFAULT_INDENT_WIDTH);
+        (IndentKind::Spaces, width)
+    }
+
+    /// Returns the width in display columns of each row's leading
+    /// whitespace, e.g. for indentation-based folding. A blank row takes
+    /// the depth of the nearest non-blank row, the deeper one on a tie, or
+    /// 0 if there is none.
+    pub fn indent_depths(&self, tab_width: usize) -> Vec<usize> {
+        let depths: Vec<Option<usize>> = self
+            .rows
+            .iter()
+            .map(|row| {
+                (!is_blank(row, true)).then(|| row.cx_to_rx(row.leading_indent().0, tab_width))
+            })
+            .collect();
+        let mut next = vec![None; depths.len()];
+        let mut following = None;
+        for (y, depth) in depths.iter().enumerate().rev() {
+            if depth.is_some() {
+                following = depth.map(|depth| (y, depth));
+            }
+            next[y] = following;
+        }
+        let mut previous = None;
+        depths
+            .iter()
+            .enumerate()
+            .map(|(y, depth)| {
+                if let Some(depth) = *depth {
+                    previous = Some((y, depth));
+                    return depth;
+                }
+                match (previous, next[y]) {
+                    (Some((above, up)), Some((below, down))) => match (y - above).cmp(&(below - y))
+                    {
+                        cmp::Ordering::Less => up,
+                        cmp::Ordering::Greater => down,
+                        cmp::Ordering::Equal => cmp::max(up, down),
+                    },
+                    (Some((_, depth)), None) | (None, Some((_, depth))) => depth,
+                    (None, None) => 0,
+                }
+            })
+            .collect()
+    }
+
+    /// Returns how many screen lines the document takes up when soft-wrapped
+    /// at `width` columns. An empty document takes up none.
+    pub fn visual_line_count(&self, width: usize, tab_width: usize) -> usize {
+        self.rows
+            .iter()
+            .map(|row| row.wrap(width, tab_width).len())
+            .sum()
+    }
+
+    /// Maps a screen line of the soft-wrapped document to the row it shows
+    /// and which of that row's wrapped segments it is, or `None` past the
+    /// end.
+    pub fn visual_to_logical(
+        &self,
+        visual_line: usize,
+        width: usize,
+        tab_width: usize,
+    ) -> Option<(usize, usize)> {
+        let mut remaining = visual_line;
+        for (y, row) in self.rows.iter().enumerate() {
+            let segments = row.wrap(width, tab_width).len();
+            if remaining < segments {
+                return Some((y, remaining));
+            }
+            remaining -= segments;
+        }
+        None
+    }
+
+    /// Renders the `height` rows from `top`, each cut to the `width` display
+    /// columns from `left` with `Row::render_cols`, ready to draw. Lines past
+    /// the end of the document are empty strings.
+    pub fn viewport(
+        &self,
+        top: usize,
+        height: usize,
+        left: usize,
+        width: usize,
+        tab_width: usize,
+    ) -> Vec<String> {
+        (top..top.saturating_add(height))
+            .map(|y| {
+                self.rows.get(y).map_or_else(String::new, |row| {
+                    row.render_cols(left, left.saturating_add(width), tab_width)
+                })
+            })
+            .collect()
+    }
+
+    /// Maps `at` to the screen line and column it is drawn at when the
+    /// document is soft-wrapped, e.g. to keep the cursor in place when
+    /// wrapping is toggled. A cursor at a wrap point starts the next line.
+    pub fn logical_to_visual(
+        &self,
+        at: &Position,
+        width: usize,
+        tab_width: usize,
+    ) -> (usize, usize) {
+        let above: usize = self
+            .rows
+            .iter()
+            .take(at.y)
+            .map(|row| row.wrap(width, tab_width).len())
+            .sum();
+        let Some(row) = self.rows.get(at.y) else {
+            return (above, 0);
+        };
+        let x = cmp::min(at.x, row.len());
+        let segments = row.wrap(width, tab_width);
+        let segment = segments
+            .iter()
+            .rposition(|&(start, _)| start <= x)
+            .unwrap_or(0);
+        let column = row.cx_to_rx(x, tab_width) - row.cx_to_rx(segments[segment].0, tab_width);
+        (above + segment, column)
+    }
+
+    /// The inverse of `logical_to_visual`: maps a screen line and column of
+    /// the soft-wrapped document to a position, clamped to that screen line,
+    /// or `None` past the end.
+    pub fn visual_to_position(
+        &self,
+        visual_line: usize,
+        visual_column: usize,
+        width: usize,
+        tab_width: usize,
+    ) -> Option<Position> {
+        let (y, segment) = self.visual_to_logical(visual_line, width, tab_width)?;
+        let row = &self.rows[y];
+        let segments = row.wrap(width, tab_width);
+        let (start, end) = segments[segment];
+        // Only the last line can hold the cursor past its final grapheme;
+        // elsewhere that spot belongs to the next line.
+        let last = if segment + 1 == segments.len() {
+            end
+        } else {
+            end - 1
+        };
+        let x = row.rx_to_cx(row.cx_to_rx(start, tab_width) + visual_column, tab_width);
+        Some(Position {
+            x: cmp::min(x, last),
+            y,
+        })
+    }
+
+    /// Returns a hash of the rows and the line ending, ignoring highlighting,
+    /// e.g. to tell whether two buffers hold the same text. It uses 64-bit
+    /// FNV-1a, so the value is the same across runs and builds.
+    pub fn content_hash(&self) -> u64 {
+        const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+        const PRIME: u64 = 0x0000_0100_0000_01b3;
+        let line_ending = self.line_ending.as_str().as_bytes();
+        self.rows
+            .iter()
+            .flat_map(|row| row.as_bytes().iter().chain(line_ending))
+            .fold(OFFSET_BASIS, |hash, &byte| {
+                (hash ^ u64::from(byte)).wrapping_mul(PRIME)
+            })
+    }
+
+    /// Returns the exact number of bytes `save` would write.
+    pub fn byte_len(&self) -> usize {
+        let newlines = if self.ensure_final_newline {
+            self.rows.len()
+        } else {
+            self.rows.len().saturating_sub(1)
+        };
+        let content: usize = self.rows.iter().map(|row| row.as_bytes().len()).sum();
+        content + newlines * self.line_ending.as_str().len()
+    }
+
+    /// Ranks rows by how well `needle` matches as a subsequence, ignoring
+    /// case, and returns where each row's best match starts with its score,
+    /// best first. Contiguous and earlier matches score higher.
+    pub fn fuzzy_find(&self, needle: &str) -> Vec<(Position, i64)> {
+        let needle: Vec<String> = needle.graphemes(true).map(str::to_lowercase).collect();
+        if needle.is_empty() {
+            return Vec::new();
+        }
+        let mut results: Vec<(Position, i64)> = self
+            .rows
+            .iter()
+            .enumerate()
+            .filter_map(|(y, row)| {
+                let graphemes: Vec<String> = row.graphemes().map(str::to_lowercase).collect();
+                (0..graphemes.len())
+                    .filter_map(|x| fuzzy_score(&graphemes[x..], &needle).map(|score| (x, score)))
+                    .map(|(x, score)| (Position { x, y }, score - x as i64))
+                    .max_by_key(|&(at, score)| (score, cmp::Reverse(at.x)))
+            })
+            .collect();
+        results.sort_by_key(|&(at, score)| (cmp::Reverse(score), at.y, at.x));
+        results
+    }
+
+    pub fn find(&self, query: &str, at: &Position, direction: SearchDirection) -> Option<Position> {
+        self.find_match(query, at, direction, SearchOptions::default())
+            .map(|found| found.start)
+    }
+
+    /// Yields the start of every match of `query` in document order,
+    /// overlapping ones included unless `options.skip_overlapping` is set.
+    /// Rows are only searched as the iterator is advanced.
+    pub fn matches<'a>(
+        &'a self,
+        query: &'a str,
+        options: SearchOptions,
+    ) -> impl Iterator<Item = Position> + 'a {
+        self.rows.iter().enumerate().flat_map(move |(y, row)| {
+            row.find_all(query, options)
+                .into_iter()
+                .map(move |x| Position { x, y })
+        })
+    }
+
+    /// Returns the index and text of the rows from `before` rows above `at`
+    /// to `after` rows below it, e.g. to preview a search result. The range
+    /// is clamped to the document.
+    pub fn context_around(
+        &self,
+        at: &Position,
+        before: usize,
+        after: usize,
+    ) -> Vec<(usize, String)> {
+        let Some(last) = self.rows.len().checked_sub(1) else {
+            return Vec::new();
+        };
+        let y = cmp::min(at.y, last);
+        let end = cmp::min(y.saturating_add(after), last);
+        (y.saturating_sub(before)..=end)
+            .map(|index| (index, self.rows[index].as_str().to_string()))
+            .collect()
+    }
+
+    /// Collects every match of `query` with the text of its row, e.g. for a
+    /// clickable results pane.
+    pub fn search_results(&self, query: &str, options: SearchOptions) -> Vec<SearchResult> {
+        let match_len = query.graphemes(true).count();
+        self.matches(query, options)
+            .map(|position| SearchResult {
+                position,
+                line: self.rows[position.y].as_str().to_string(),
+                match_len,
+            })
+            .collect()
+    }
+
+    /// Returns the indices of the rows containing `query`, e.g. for a
+    /// grep-style panel built with `row`.
+    pub fn filtered_rows(&self, query: &str, options: SearchOptions) -> Vec<usize> {
+        if query.is_empty() {
+            return Vec::new();
+        }
+        self.rows
+            .iter()
+            .enumerate()
+            .filter(|(_, row)| {
+                row.find_match(query, 0, SearchDirection::Forward, options)
+                    .is_some()
+            })
+            .map(|(y, _)| y)
+            .collect()
+    }
+
+    /// Collects `matches` into a `Vec`.
+    pub fn find_all(&self, query: &str, options: SearchOptions) -> Vec<Position> {
+        self.matches(query, options).collect()
+    }
+
+    /// Like `find`, but honours `options` and also reports how many
+    /// graphemes (or codepoints, see `set_cursor_model`) the match spans,
+    /// which the editor uses to place the selection.
+    pub fn find_match(
+        &self,
+        query: &str,
+        at: &Position,
+        direction: SearchDirection,
+        options: SearchOptions,
+    ) -> Option<Match> {
+        let at = self.to_graphemes(at, self.cursor_model);
+        let found = self.find_grapheme_match(query, &at, direction, options)?;
+        let row = &self.rows[found.start.y];
+        let x = row.to_model_index(found.start.x, self.cursor_model);
+        Some(Match {
+            start: Position {
+                x,
+                y: found.start.y,
+            },
+            len: row.to_model_index(found.start.x + found.len, self.cursor_model) - x,
+        })
+    }
+
+    fn find_grapheme_match(
+        &self,
+        query: &str,
+        at: &Position,
+        direction: SearchDirection,
+        options: SearchOptions,
+    ) -> Option<Match> {
+        let row = self.rows.get(at.y)?;
+        // A cursor can sit past the end of its row after moving down from a
+        // longer one; clamp it so the current row still gets searched.
+        let mut position = Position {
+            x: at.x.min(row.len()),
+            y: at.y,
+        };
+        let start = if direction == SearchDirection::Forward {
+            at.y
+        } else {
+            0
+        };
+        let end = if direction == SearchDirection::Forward {
+            self.rows.len()
+        } else {
+            at.y.saturating_add(1)
+        };
+        for _ in start..end {
+            let row = self.rows.get(position.y)?;
+            if let Some((x, len)) = row.find_match(query, position.x, direction, options) {
+                position.x = x;
+                return Some(Match {
+                    start: position,
+                    len,
+                });
+            }
+            if direction == SearchDirection::Forward {
+                position.y = position.y.saturating_add(1);
+                position.x = 0;
+            } else {
+                position.y = position.y.saturating_sub(1);
+                position.x = self.rows[position.y].len();
+            }
+        }
+        None
+    }
+
+    /// Re-highlights every row, marking matches of `word` if there is one.
+    pub fn highlight(&mut self, word: Option<&str>, options: SearchOptions) {
+        for index in 0..self.rows.len() {
+            self.highlight_row(index, word, options);
+        }
+    }
+
+    /// Re-highlights the row at `index` with the current syntax, e.g. after
+    /// an edit made outside `insert`/`delete`.
+    pub fn highlight_row(&mut self, index: usize, word: Option<&str>, options: SearchOptions) {
+        let Some(row) = self.rows.get_mut(index) else {
+            return;
+        };
+        // `Row::highlight` paints matches found by the same `find_match`,
+        // so a row is dimmed exactly when it shows no match.
+        let dimmed = self.dim_unmatched
+            && word.is_some_and(|word| {
+                !word.is_empty()
+                    && row
+                        .find_match(word, 0, SearchDirection::Forward, options)
+                        .is_none()
+            });
+        let word =
+            word.filter(|_| !self.hide_active_line_matches || self.active_line != Some(index));
+        row.highlight(&self.syntax, word, options);
+        if dimmed {
+            for x in 0..row.len() {
+                row.set_highlight(x, highlighting::Type::Dimmed);
+            }
+        }
+        self.reapply_bracket_highlight(index);
+    }
+
+    /// Re-highlights every row, marking the matches of each term with the
+    /// type paired with it. Later terms win where matches overlap.
+    pub fn highlight_terms(
+        &mut self,
+        terms: &[(String, highlighting::Type)],
+        options: SearchOptions,
+    ) {
+        for index in 0..self.rows.len() {
+            self.rows[index].highlight_terms(&self.syntax, terms, options);
+            self.reapply_bracket_highlight(index);
+        }
+    }
+
+    /// Marks the matched brackets on row `index` again after it was
+    /// re-highlighted, remembering the types they would otherwise have.
+    fn reapply_bracket_highlight(&mut self, index: usize) {
+        let Some(row) = self.rows.get_mut(index) else {
+            return;
+        };
+        for (at, previous) in &mut self.bracket_highlight {
+            if at.y != index {
+                continue;
+            }
+            if let Some(current) = row.highlight_at(at.x) {
+                *previous = current;
+                row.set_highlight(at.x, highlighting::Type::MatchBracket);
+            }
+        }
+    }
+
+    /// Returns the grapheme cluster at `at`, or `None` if the position is
+    /// out of range.
+    pub fn grapheme_at(&self, at: &Position) -> Option<&str> {
+        self.rows.get(at.y)?.grapheme_at(at.x)
+    }
+
+    /// Returns the highlighting of the grapheme at `at`, or `Type::None` if
+    /// the position is out of range.
+    pub fn highlight_at(&self, at: &Position) -> highlighting::Type {
+        self.rows
+            .get(at.y)
+            .and_then(|row| row.highlight_at(at.x))
+            .unwrap_or(highlighting::Type::None)
+    }
+
+    /// Lists the TODO keywords (see `Syntax::todo_keywords`) found in
+    /// comments, with where each starts and the rest of its comment: the
+    /// graphemes up to where the comment highlighting ends.
+    pub fn todos(&self) -> Vec<(Position, String)> {
+        let mut todos = Vec::new();
+        for (y, row) in self.rows.iter().enumerate() {
+            let mut previous = None;
+            for x in 0..row.len() {
+                let current = row.highlight_at(x);
+                if current == Some(highlighting::Type::Todo) && previous != current {
+                    let text: String = row
+                        .graphemes()
+                        .enumerate()
+                        .skip(x)
+                        .take_while(|&(x, _)| self.is_comment_text(&Position { x, y }))
+                        .map(|(_, grapheme)| grapheme)
+                        .collect();
+                    todos.push((Position { x, y }, text.trim_end().to_string()));
+                }
+                previous = current;
+            }
+        }
+        todos
+    }
+
+    /// Returns whether `at` belongs to a comment, including links and
+    /// search matches drawn over it.
+    fn is_comment_text(&self, at: &Position) -> bool {
+        self.context_at(at) == SyntaxContext::Comment
+            || matches!(
+                self.highlight_at(at),
+                highlighting::Type::Url
+                    | highlighting::Type::Search
+                    | highlighting::Type::SearchFuzzy
+            )
+    }
+
+    /// Returns whether `at` is in code, a string or a comment, going by the
+    /// highlighting already computed for its row. A matched bracket counts
+    /// as what it was before being marked.
+    pub fn context_at(&self, at: &Position) -> SyntaxContext {
+        let highlighting = self
+            .bracket_highlight
+            .iter()
+            .find(|(position, _)| position == at)
+            .map_or_else(|| self.highlight_at(at), |&(_, previous)| previous);
+        match highlighting {
+            highlighting::Type::String => SyntaxContext::String,
+            highlighting::Type::Comment | highlighting::Type::Todo => SyntaxContext::Comment,
+            _ => SyntaxContext::Code,
+        }
+    }
+
+    /// Sets the row whose spans carry the `ActiveLine` background, usually
+    /// the one the cursor is on.
+    pub fn set_active_line(&mut self, y: Option<usize>) {
+        if y == self.active_line {
+            return;
+        }
+        for index in [self.active_line, y].into_iter().flatten() {
+            if let Some(row) = self.rows.get_mut(index) {
+                row.request_redraw();
+            }
+        }
+        self.active_line = y;
+    }
+
+    /// Returns the indices of rows changed since they were last drawn,
+    /// including rows that moved because lines were added or removed above
+    /// them.
+    pub fn dirty_rows(&self) -> Vec<usize> {
+        self.rows
+            .iter()
+            .enumerate()
+            .filter(|(_, row)| row.needs_redraw())
+            .map(|(index, _)| index)
+            .collect()
+    }
+
+    /// Clears the redraw flag of row `index` once the editor has drawn it.
+    pub fn mark_drawn(&mut self, index: usize) {
+        if let Some(row) = self.rows.get_mut(index) {
+            row.mark_drawn();
+        }
+    }
+
+    fn request_redraw_from(&mut self, y: usize) {
+        for row in self.rows.iter_mut().skip(y) {
+            row.request_redraw();
+        }
+    }
+
+    /// Returns the spans of the graphemes `[start, end)` of row `index`,
+    /// tinted with the `ActiveLine` background if it is the active line.
+    pub fn render_spans(&self, index: usize, start: usize, end: usize) -> Vec<Span> {
+        let Some(row) = self.rows.get(index) else {
+            return Vec::new();
+        };
+        let mut spans = row.render_spans(start, end);
+        if self.active_line == Some(index) {
+            for span in &mut spans {
+                span.background = Some(highlighting::Type::ActiveLine);
+            }
+        }
+        spans
+    }
+
+    /// Returns the position of the bracket matching the one at `at`, if `at`
+    /// is on a bracket and its partner exists.
+    pub fn matching_bracket(&self, at: &Position) -> Option<Position> {
+        let current = self.rows.get(at.y)?.as_str().graphemes(true).nth(at.x)?;
+        let (open, close, forward) = BRACKETS.iter().find_map(|&(open, close)| {
+            if current == open {
+                Some((open, close, true))
+            } else if current == close {
+                Some((open, close, false))
+            } else {
+                None
+            }
+        })?;
+        let mut depth = 0;
+        if forward {
+            for (y, row) in self.rows.iter().enumerate().skip(at.y) {
+                let skip = if y == at.y { at.x } else { 0 };
+                for (x, grapheme) in row.as_str().graphemes(true).enumerate().skip(skip) {
+                    if grapheme == open {
+                        depth += 1;
+                    } else if grapheme == close {
+                        depth -= 1;
+                        if depth == 0 {
+                            return Some(Position { x, y });
+                        }
+                    }
+                }
+            }
+        } else {
+            for y in (0..=at.y).rev() {
+                let graphemes: Vec<&str> = self.rows[y].as_str().graphemes(true).collect();
+                let end = if y == at.y { at.x + 1 } else { graphemes.len() };
+                for x in (0..end).rev() {
+                    if graphemes[x] == close {
+                        depth += 1;
+                    } else if graphemes[x] == open {
+                        depth -= 1;
+                        if depth == 0 {
+                            return Some(Position { x, y });
+                        }
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    /// Marks the two graphemes of a bracket pair as `MatchBracket`, replacing
+    /// any pair marked before. Passing `None` clears the mark.
+    pub fn set_bracket_highlight(&mut self, pair: Option<(Position, Position)>) {
+        while let Some((at, previous)) = self.bracket_highlight.pop() {
+            if let Some(row) = self.rows.get_mut(at.y) {
+                if row.highlight_at(at.x) == Some(highlighting::Type::MatchBracket) {
+                    row.set_highlight(at.x, previous);
+                }
+            }
+        }
+        if let Some((a, b)) = pair {
+            for at in [a, b] {
+                if let Some(row) = self.rows.get_mut(at.y) {
+                    if let Some(previous) = row.highlight_at(at.x) {
+                        row.set_highlight(at.x, highlighting::Type::MatchBracket);
+                        self.bracket_highlight.push((at, previous));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Breaks the line at `at` and returns where the cursor goes: the start
+    /// of the following line. On the virtual line past the end of the
+    /// document this only appends an empty row; on a real row the text from
+    /// `at.x` (clamped to the row) onwards moves to the new row, after the
+    /// comment continuation if `continue_comments` is on.
+    pub fn insert_newline(&mut self, at: &Position) -> Result<Position, EditError> {
+        self.check_position(at)?;
+        let at = self.to_graphemes(at, self.cursor_model);
+        let next = self.insert_grapheme_newline(&at);
+        Ok(Position {
+            x: self
+                .rows
+                .get(next.y)
+                .map_or(next.x, |row| row.to_model_index(next.x, self.cursor_model)),
+            y: next.y,
+        })
+    }
+
+    /// `insert_newline` with `at.x` counted in graphemes.
+    fn insert_grapheme_newline(&mut self, at: &Position) -> Position {
+        let mut x = 0;
+        if at.y == self.rows.len() {
+            self.record(at, 0, 1);
+            self.rows.push(Row::default());
+        } else {
+            self.record(at, 1, 2);
+            let continuation = self.comment_continuation(at);
+            let current_row = &mut self.rows[at.y];
+            let mut new_row = current_row.split(at.x.min(current_row.len()));
+            if let Some(continuation) = continuation {
+                x = continuation.graphemes(true).count();
+                new_row = Row::from(&format!("{continuation}{}", new_row.as_str())[..]);
+            }
+            current_row.highlight(&self.syntax, None, SearchOptions::default());
+            new_row.highlight(&self.syntax, None, SearchOptions::default());
+            self.rows.insert(at.y + 1, new_row);
+            self.request_redraw_from(at.y + 1);
+        }
+        self.mark_dirty();
+        Position { x, y: at.y + 1 }
+    }
+
+    /// Returns the indentation and comment marker, followed by a space, to
+    /// start the next line with when breaking row `at.y` after its line
+    /// comment marker.
+    fn comment_continuation(&self, at: &Position) -> Option<String> {
+        if !self.continue_comments {
+            return None;
+        }
+        let marker = self.syntax.line_comment()?;
+        let row = self.rows.get(at.y)?;
+        let (indent, _) = row.leading_indent();
+        if !row.as_str()[indent..].starts_with(marker)
+            || at.x < indent + marker.graphemes(true).count()
+        {
+            return None;
+        }
+        Some(format!("{}{marker} ", &row.as_str()[..indent]))
+    }
+
+    pub fn insert(&mut self, at: &Position, c: char) -> Result<(), EditError> {
+        self.insert_in(at, c, self.cursor_model)
+    }
+
+    fn insert_in(&mut self, at: &Position, c: char, model: CursorModel) -> Result<(), EditError> {
+        self.check_position(at)?;
+        if c == '\n' {
+            self.insert_grapheme_newline(&self.to_graphemes(at, model));
+            return Ok(());
+        }
+        if at.y == self.rows.len() {
+            self.record(at, 0, 1);
+            self.rows.push(Row::default());
+        } else {
+            self.record(at, 1, 1);
+        }
+        let row = &mut self.rows[at.y];
+        row.insert_in(at.x, c, model);
+        row.highlight(&self.syntax, None, SearchOptions::default());
+        self.mark_dirty();
+        Ok(())
+    }
+
+    /// Inserts `c` as typed by the user and returns where the cursor goes.
+    /// When the text before the cursor then ends with one of the syntax's
+    /// auto-pair openers, the matching closer is inserted after the cursor,
+    /// and both undo as one step. With `virtual_space` on, typing past the
+    /// end of a row first pads it with spaces up to the cursor.
+    pub fn type_char(&mut self, at: &Position, c: char) -> Result<Position, EditError> {
+        self.check_position(at)?;
+        if c == '\n' {
+            return Ok(self.insert_grapheme_newline(at));
+        }
+        let mut cursor = Position {
+            x: self.rows.get(at.y).map_or(0, |row| at.x.min(row.len())),
+            y: at.y,
+        };
+        self.begin_transaction();
+        if self.virtual_space && at.x > cursor.x && at.y < self.rows.len() {
+            self.record(at, 1, 1);
+            let row = &mut self.rows[at.y];
+            row.insert_padded(at.x, c);
+            row.highlight(&self.syntax, None, SearchOptions::default());
+            cursor.x = row.len();
+            self.mark_dirty();
+        } else {
+            cursor.x = self.insert_chars(&cursor, &c.to_string());
+        }
+        let row = &self.rows[cursor.y];
+        let before: String = row.graphemes().take(cursor.x).collect();
+        let close = self
+            .syntax
+            .auto_pairs()
+            .iter()
+            .find(|(open, _)| open.ends_with(c) && before.ends_with(open.as_str()))
+            .map(|(_, close)| close.clone());
+        if let Some(close) = close {
+            self.insert_chars(&cursor, &close);
+        }
+        self.commit_transaction();
+        Ok(cursor)
+    }
+
+    /// Types `c` at every selection, replacing the selected text, like
+    /// `type_char` with several cursors. Columns are in graphemes. Each
+    /// selection collapses to a cursor just past its `c`, and overlapping
+    /// selections are merged first. The whole edit undoes as one step.
+    pub fn type_multi(&mut self, cursors: &mut Vec<Selection>, c: char) -> Result<(), EditError> {
+        for selection in cursors.iter() {
+            self.check_position(&selection.anchor)?;
+            self.check_position(&selection.cursor)?;
+        }
+        let mut ranges: Vec<(Position, Position)> = cursors.iter().map(Selection::range).collect();
+        ranges.sort_by_key(|(start, _)| (start.y, start.x));
+        let mut merged: Vec<(Position, Position)> = Vec::with_capacity(ranges.len());
+        for (start, end) in ranges {
+            match merged.last_mut() {
+                Some((last_start, last_end))
+                    if (start.y, start.x) < (last_end.y, last_end.x) || start == *last_start =>
+                {
+                    if (end.y, end.x) > (last_end.y, last_end.x) {
+                        *last_end = end;
+                    }
+                }
+                _ => merged.push((start, end)),
+            }
+        }
+        // Work backwards so the selections still to be handled keep their
+        // positions; only the cursors already placed after them shift.
+        let mut typed: Vec<Position> = Vec::with_capacity(merged.len());
+        self.begin_transaction();
+        for (start, end) in merged.into_iter().rev() {
+            let (start, end) = (self.clamp_position(&start), self.clamp_position(&end));
+            self.delete_range(&start, &end);
+            let next = if c == '\n' {
+                self.insert_grapheme_newline(&start)
+            } else {
+                Position {
+                    x: self.insert_chars(&start, &c.to_string()),
+                    y: start.y,
+                }
+            };
+            for cursor in &mut typed {
+                if cursor.y == end.y {
+                    cursor.x = next.x + (cursor.x - end.x);
+                }
+                cursor.y = cursor.y - end.y + next.y;
+            }
+            typed.push(next);
+        }
+        self.commit_transaction();
+        *cursors = typed
+            .into_iter()
+            .rev()
+            .map(|at| Selection {
+                anchor: at,
+                cursor: at,
+            })
+            .collect();
+        Ok(())
+    }
+
+    /// Clamps `at.x` to the length of its row, or to 0 on the virtual line.
+    fn clamp_position(&self, at: &Position) -> Position {
+        Position {
+            x: self.rows.get(at.y).map_or(0, |row| at.x.min(row.len())),
+            y: at.y,
+        }
+    }
+
+    /// Removes the text from `start` up to `end`, both clamped positions in
+    /// graphemes with `start` first, as one recorded edit.
+    fn delete_range(&mut self, start: &Position, end: &Position) {
+        if start == end || start.y >= self.rows.len() {
+            return;
+        }
+        let end = if end.y < self.rows.len() {
+            *end
+        } else {
+            let y = self.rows.len() - 1;
+            Position {
+                x: self.rows[y].len(),
+                y,
+            }
+        };
+        self.record(start, end.y - start.y + 1, 1);
+        let tail: String = self.rows[end.y].graphemes().skip(end.x).collect();
+        let row = &mut self.rows[start.y];
+        row.replace_range(start.x, row.len(), &tail);
+        row.highlight(&self.syntax, None, SearchOptions::default());
+        if end.y > start.y {
+            self.rows.drain(start.y + 1..=end.y);
+            self.request_redraw_from(start.y + 1);
+        }
+        self.mark_dirty();
+    }
+
+    /// Inserts the characters of a single line of `text` at `at`, which must
+    /// be inside the document, and returns the grapheme index just past
+    /// them.
+    fn insert_chars(&mut self, at: &Position, text: &str) -> usize {
+        let mut x = at.x;
+        for c in text.chars() {
+            let before = self.rows.get(at.y).map_or(0, Row::len);
+            self.insert_in(&Position { x, y: at.y }, c, CursorModel::Grapheme)
+                .expect("the position is inside the document");
+            x += self.rows[at.y].len() - before;
+        }
+        x
+    }
+
+    /// Inserts `text`, which may span several lines, and returns the
+    /// position just past it. `options` only changes the inserted text,
+    /// never what was already in the rows.
+    pub fn insert_str(
+        &mut self,
+        at: &Position,
+        text: &str,
+        options: PasteOptions,
+    ) -> Result<Position, EditError> {
+        self.check_position(at)?;
+        let mut cursor = Position {
+            x: self.rows.get(at.y).map_or(0, |row| at.x.min(row.len())),
+            y: at.y,
+        };
+        let at_row_end = self.rows.get(at.y).is_none_or(|row| cursor.x == row.len());
+        let mut lines: Vec<&str> = text
+            .split('\n')
+            .map(|line| line.strip_suffix('\r').unwrap_or(line))
+            .collect();
+        if options.trim_trailing_whitespace {
+            let last = lines.len() - 1;
+            for (index, line) in lines.iter_mut().enumerate() {
+                if index < last || at_row_end {
+                    *line = line.trim_end();
+                }
+            }
+        }
+        let mut indent = String::new();
+        let mut common = 0;
+        if options.reindent {
+            let row = self.rows.get(at.y);
+            indent = row
+                .map_or("", |row| &row.as_str()[..row.leading_indent().0])
+                .to_string();
+            common = lines[1..]
+                .iter()
+                .filter(|line| !line.trim().is_empty())
+                .map(|line| line.len() - line.trim_start_matches([' ', '\t']).len())
+                .min()
+                .unwrap_or(0);
+        }
+        // Pasted text keeps its own comment markers.
+        let continue_comments = std::mem::replace(&mut self.continue_comments, false);
+        self.begin_transaction();
+        for (index, line) in lines.iter().enumerate() {
+            if index > 0 {
+                cursor = self.insert_grapheme_newline(&cursor);
+                if options.reindent && !line.trim().is_empty() {
+                    cursor.x = self.insert_chars(&cursor, &indent);
+                }
+            }
+            let line = if index > 0 && options.reindent {
+                line.get(common..).unwrap_or("")
+            } else {
+                line
+            };
+            cursor.x = self.insert_chars(&cursor, line);
+        }
+        self.commit_transaction();
+        self.continue_comments = continue_comments;
+        Ok(cursor)
+    }
+
+    /// Inserts `template`, in which `$1`, `$2`, ... and `$0` mark placeholder
+    /// stops. The markers are left out of the inserted text. Returns the
+    /// position just past the inserted text and the placeholder positions in
+    /// stop order: `$1`, `$2`, ..., then `$0`.
+    pub fn insert_snippet(
+        &mut self,
+        at: &Position,
+        template: &str,
+    ) -> Result<(Position, Vec<Position>), EditError> {
+        self.check_position(at)?;
+        let mut cursor = Position {
+            x: self.rows.get(at.y).map_or(0, |row| at.x.min(row.len())),
+            y: at.y,
+        };
+        let mut placeholders = Vec::new();
+        let mut chars = template.chars().peekable();
+        self.begin_transaction();
+        while let Some(c) = chars.next() {
+            if c == '$' && chars.peek().is_some_and(char::is_ascii_digit) {
+                let mut number = 0;
+                while let Some(digit) = chars.peek().and_then(|c| c.to_digit(10)) {
+                    number = number * 10 + digit as usize;
+                    chars.next();
+                }
+                placeholders.push((number, cursor));
+                continue;
+            }
+            if c == '\n' {
+                // The new row may start with a continued comment leader.
+                cursor = self.insert_grapheme_newline(&cursor);
+                continue;
+            }
+            let before = self.rows.get(cursor.y).map_or(0, Row::len);
+            self.insert_in(&cursor, c, CursorModel::Grapheme)
+                .expect("the cursor stays inside the document");
+            cursor.x += self.rows[cursor.y].len() - before;
+        }
+        self.commit_transaction();
+        placeholders.sort_by_key(|&(number, _)| if number == 0 { usize::MAX } else { number });
+        let placeholders = placeholders.into_iter().map(|(_, at)| at).collect();
+        Ok((cursor, placeholders))
+    }
+
+    /// Deletes the grapheme at `at`, or joins the next row onto this one at
+    /// the end of a line. Deleting at the end of the last row or on the
+    /// virtual line past it does nothing and leaves the dirty flag alone.
+    pub fn delete(&mut self, at: &Position) -> Result<(), EditError> {
+        self.check_position(at)?;
+        let len = self.rows.len();
+        if at.y == len {
+            return Ok(());
+        }
+        let at_line_end = at.x >= self.rows[at.y].len_in(self.cursor_model);
+        if at_line_end && at.y + 1 == len {
+            return Ok(());
+        }
+        if at_line_end {
+            self.record(at, 2, 1);
+            let next_row = self.rows.remove(at.y + 1);
+            self.request_redraw_from(at.y + 1);
+            let row = &mut self.rows[at.y];
+            row.append(&next_row);
+            row.highlight(&self.syntax, None, SearchOptions::default());
+        } else {
+            self.record(at, 1, 1);
+            let row = &mut self.rows[at.y];
+            row.delete_in(at.x, self.cursor_model);
+            row.highlight(&self.syntax, None, SearchOptions::default());
+        }
+        self.mark_dirty();
+        Ok(())
+    }
+
+    /// Removes trailing whitespace from row `y`, e.g. when the cursor leaves
+    /// it, and returns whether anything was removed. The active line, where
+    /// the cursor still is, is left alone.
+    pub fn trim_row_end(&mut self, y: usize) -> bool {
+        if Some(y) == self.active_line {
+            return false;
+        }
+        let Some(row) = self.rows.get(y) else {
+            return false;
+        };
+        let trimmed = row.as_str().trim_end();
+        if trimmed.len() == row.as_str().len() {
+            return false;
+        }
+        let at = Position {
+            x: trimmed.graphemes(true).count(),
+            y,
+        };
+        self.record(&at, 1, 1);
+        let row = &mut self.rows[y];
+        row.split(at.x);
+        row.highlight(&self.syntax, None, SearchOptions::default());
+        self.mark_dirty();
+        true
+    }
+
+    /// Deletes the rectangle of graphemes between columns `start.x` and
+    /// `end.x` (exclusive) on every row from `start.y` to `end.y`, e.g. for
+    /// column-mode editing. Rows that end before the left column are left
+    /// alone. The corners may be given in either order.
+    pub fn delete_block(&mut self, start: &Position, end: &Position) {
+        let (left, right) = (cmp::min(start.x, end.x), cmp::max(start.x, end.x));
+        let top = cmp::min(start.y, end.y);
+        let bottom = cmp::min(cmp::max(start.y, end.y), self.rows.len().saturating_sub(1));
+        if left == right || top >= self.rows.len() {
+            return;
+        }
+        if !self.rows[top..=bottom].iter().any(|row| row.len() > left) {
+            return;
+        }
+        self.record(
+            &Position { x: left, y: top },
+            bottom - top + 1,
+            bottom - top + 1,
+        );
+        for row in &mut self.rows[top..=bottom] {
+            if row.len() <= left {
+                continue;
+            }
+            let text: String = row
+                .grapheme_indices()
+                .filter(|&(x, _)| x < left || x >= right)
+                .map(|(_, grapheme)| grapheme)
+                .collect();
+            *row = Row::from(&text[..]);
+            row.highlight(&self.syntax, None, SearchOptions::default());
+        }
+        self.mark_dirty();
+    }
+
+    /// Appends the lines of `text` after the last row, e.g. new output of a
+    /// followed log. A trailing newline does not add an empty row, just like
+    /// in `open`.
+    pub fn append_str(&mut self, text: &str) {
+        let rows = highlighted_rows(text.lines(), &self.syntax);
+        if rows.is_empty() {
+            return;
+        }
+        let at = Position {
+            x: 0,
+            y: self.rows.len(),
+        };
+        self.record(&at, 0, rows.len());
+        self.rows.extend(rows);
+        self.mark_dirty();
+    }
+
+    /// Moves the rows of `other` onto the end of this document, highlighted
+    /// with this document's syntax. The filename and settings stay this
+    /// document's.
+    pub fn append_document(&mut self, other: Document) {
+        if other.rows.is_empty() {
+            return;
+        }
+        let at = Position {
+            x: 0,
+            y: self.rows.len(),
+        };
+        self.record(&at, 0, other.rows.len());
+        for mut row in other.rows {
+            row.highlight(&self.syntax, None, SearchOptions::default());
+            row.request_redraw();
+            self.rows.push(row);
+        }
+        self.mark_dirty();
+    }
+
+    /// Keeps only the last `n` rows, e.g. for following a growing log. Does
+    /// nothing if there are no more than `n` rows.
+    pub fn truncate_to_last(&mut self, n: usize) {
+        let Some(dropped) = self
+            .rows
+            .len()
+            .checked_sub(n)
+            .filter(|&dropped| dropped > 0)
+        else {
+            return;
+        };
+        self.record(&Position::default(), dropped, 0);
+        self.rows.drain(..dropped);
+        self.bracket_highlight.retain_mut(|(at, _)| {
+            at.y = at.y.wrapping_sub(dropped);
+            at.y < n
+        });
+        self.active_line = self.active_line.and_then(|y| y.checked_sub(dropped));
+        self.request_redraw_from(0);
+        self.mark_dirty();
+    }
+
+    /// Deletes the grapheme before `at`, joining the row onto the previous
+    /// one at the start of a line, and returns the new cursor position. A
+    /// cursor past the end of its row deletes the row's last grapheme.
+    /// Nothing happens at the very start of the document.
+    pub fn backspace(&mut self, at: &Position) -> Result<Position, EditError> {
+        self.check_position(at)?;
+        let x = self
+            .rows
+            .get(at.y)
+            .map_or(0, |row| cmp::min(at.x, row.len_in(self.cursor_model)));
+        let previous = if x > 0 {
+            Position { x: x - 1, y: at.y }
+        } else if at.y > 0 {
+            Position {
+                x: self.rows[at.y - 1].len_in(self.cursor_model),
+                y: at.y - 1,
+            }
+        } else {
+            return Ok(*at);
+        };
+        if at.y < self.rows.len() {
+            self.delete(&previous)?;
+        }
+        Ok(previous)
+    }
+
+    /// Rejects positions below the virtual line just past the end of the
+    /// document. Columns are not checked; rows clamp them.
+    fn check_position(&self, at: &Position) -> Result<(), EditError> {
+        if at.y > self.rows.len() {
+            return Err(EditError::OutOfBounds(*at));
+        }
+        Ok(())
+    }
+
+    /// Reverts the most recent undo step and returns the position of the
+    /// first edit in it, or `None` if there is nothing to undo.
+    pub fn undo(&mut self) -> Option<Position> {
+        let edits = self.undo_stack.pop()?;
+        let mut cursor = None;
+        for edit in edits.into_iter().rev() {
+            let rows = highlighted_rows(edit.removed.iter().map(String::as_str), &self.syntax);
+            let end = cmp::min(edit.at.y + edit.inserted, self.rows.len());
+            let shifted = rows.len() != end - edit.at.y;
+            self.rows.splice(edit.at.y..end, rows);
+            if shifted {
+                self.request_redraw_from(edit.at.y);
+            }
+            cursor = Some(edit.at);
+        }
+        self.mark_dirty();
+        cursor
+    }
+
+    /// Starts grouping edits into a single undo step, which ends at the
+    /// matching `commit_transaction`. Transactions may nest; only the
+    /// outermost commit closes the group.
+    pub fn begin_transaction(&mut self) {
+        if self.transaction_depth == 0 && self.max_undo > 0 {
+            self.undo_stack.push(Vec::new());
+            self.trim_undo_stack();
+        }
+        self.transaction_depth += 1;
+    }
+
+    pub fn commit_transaction(&mut self) {
+        if self.transaction_depth == 0 {
+            return;
+        }
+        self.transaction_depth -= 1;
+        if self.transaction_depth == 0 && self.undo_stack.last().is_some_and(Vec::is_empty) {
+            self.undo_stack.pop();
+        }
+    }
+
+    /// Remembers the `removed` rows starting at `at.y`, which the edit about
+    /// to be made at `at` replaces with `inserted` rows.
+    fn record(&mut self, at: &Position, removed: usize, inserted: usize) {
+        if self.max_undo == 0 {
+            return;
+        }
+        let edit = Edit {
+            at: *at,
+            removed: self.rows[at.y..at.y + removed]
+                .iter()
+                .map(|row| row.as_str().to_string())
+                .collect(),
+            inserted,
+        };
+        match self.undo_stack.last_mut() {
+            Some(edits) if self.transaction_depth > 0 => edits.push(edit),
+            _ => {
+                self.undo_stack.push(vec![edit]);
+                self.trim_undo_stack();
+            }
+        }
+    }
+
+    pub fn max_undo(&self) -> usize {
+        self.max_undo
+    }
+
+    /// Caps the number of undo steps kept, dropping the oldest ones beyond
+    /// it. Zero turns undo off.
+    pub fn set_max_undo(&mut self, max_undo: usize) {
+        self.max_undo = max_undo;
+        self.trim_undo_stack();
+    }
+
+    fn trim_undo_stack(&mut self) {
+        let excess = self.undo_stack.len().saturating_sub(self.max_undo);
+        self.undo_stack.drain(..excess);
+    }
+
+    /// Writes the document to its file. The rows go to a temporary file
+    /// next to the target first, which is then renamed over it, so a failed
+    /// or interrupted save never leaves a half-written file behind. A clean
+    /// buffer is not written back to the file it was opened from or last
+    /// saved to, so watch tools see no spurious change. Saving a document
+    /// without a filename fails with `ErrorKind::InvalidInput`.
+    pub fn save(&mut self) -> Result<(), io::Error> {
+        self.save_with_progress(|_, _| {})
+    }
+
+    /// Like `save`, but writes even a clean buffer, e.g. after changing
+    /// only the line ending.
+    pub fn force_save(&mut self) -> Result<(), io::Error> {
+        self.write_and_rename(|_, _| {})
+    }
+
+    /// Like `save`, but calls `progress(rows_written, total_rows)` every
+    /// few thousand rows and once more when all rows are written, e.g. to
+    /// drive a progress bar for huge files.
+    pub fn save_with_progress<F: FnMut(usize, usize)>(
+        &mut self,
+        progress: F,
+    ) -> Result<(), io::Error> {
+        let unchanged = !self.dirty
+            && self.saved_filename == self.filename
+            && self
+                .filename
+                .as_ref()
+                .is_some_and(|filename| Path::new(filename).exists());
+        if unchanged {
+            return Ok(());
+        }
+        self.write_and_rename(progress)
+    }
+
+    fn write_and_rename<F: FnMut(usize, usize)>(
+        &mut self,
+        mut progress: F,
+    ) -> Result<(), io::Error> {
+        let Some(filename) = &self.filename else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "document has no filename",
+            ));
+        };
+        let path = Path::new(filename);
+        let temp_path = temp_path(path);
+        let written = self
+            .write_file(&temp_path, &mut progress)
+            .and_then(|()| copy_permissions(path, &temp_path))
+            .and_then(|()| fs::rename(&temp_path, path));
+        if let Err(error) = written {
+            let _ = fs::remove_file(&temp_path);
+            return Err(error);
+        }
+        self.saved_filename = Some(filename.clone());
+        self.dirty = false;
+        self.remove_swap()
+    }
+
+    /// Saves the document if it has unsaved changes and a filename to save
+    /// to, returning whether a save happened. Meant to be called on a timer.
+    pub fn auto_save(&mut self) -> Result<bool, io::Error> {
+        if !self.dirty || self.filename.is_none() {
+            return Ok(false);
+        }
+        self.save()?;
+        Ok(true)
+    }
+
+    /// Writes the buffer to the swap file next to the document: the original
+    /// path on the first line, `lf` or `crlf` on the second, followed by the
+    /// rows. See `update_swap` for keeping it current while editing.
+    pub fn write_swap(&self) -> Result<(), io::Error> {
+        let Some(filename) = &self.filename else {
+            return Ok(());
+        };
+        let mut file = fs::File::create(swap_path(Path::new(filename)))?;
+        writeln!(file, "{filename}")?;
+        let line_ending = match self.line_ending {
+            LineEnding::Lf => "lf",
+            LineEnding::CrLf => "crlf",
+        };
+        writeln!(file, "{line_ending}")?;
+        for row in &self.rows {
+            file.write_all(row.as_bytes())?;
+            file.write_all(b"\n")?;
+        }
+        Ok(())
+    }
+
+    /// Rewrites the swap file if the buffer changed since it was last
+    /// written, returning whether it did. The first edit writes it right
+    /// away; call this on a timer, like `auto_save`, to capture later edits.
+    pub fn update_swap(&mut self) -> Result<bool, io::Error> {
+        if !self.swap_stale || !self.dirty || self.filename.is_none() {
+            return Ok(false);
+        }
+        self.write_swap()?;
+        self.swap_stale = false;
+        Ok(true)
+    }
+
+    /// Deletes the swap file, if any. Call this when closing the document
+    /// on purpose, e.g. when quitting without saving.
+    pub fn remove_swap(&self) -> Result<(), io::Error> {
+        let Some(filename) = &self.filename else {
+            return Ok(());
+        };
+        match fs::remove_file(swap_path(Path::new(filename))) {
+            Err(error) if error.kind() != io::ErrorKind::NotFound => Err(error),
+            _ => Ok(()),
+        }
+    }
+
+    /// Marks the buffer as modified after an edit has been applied: drops
+    /// cached layout, marks the swap file stale and writes it on the first
+    /// edit since the last save.
+    fn mark_dirty(&mut self) {
+        self.max_line_width.set(None);
+        self.swap_stale = true;
+        if !self.dirty {
+            self.dirty = true;
+            // The swap file is best effort; failing to write it must not
+            // get in the way of editing.
+            let _ = self.update_swap();
+        }
+    }
+
+    fn write_file<F: FnMut(usize, usize)>(
+        &self,
+        path: &Path,
+        progress: &mut F,
+    ) -> Result<(), io::Error> {
+        let mut file = fs::File::create(path)?;
+        self.write_rows(&mut file, progress)?;
+        file.sync_all()
+    }
+
+    /// Writes all rows, separated by the document's line ending, to any
+    /// writer. Unlike `save`, this leaves the dirty flag alone.
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> Result<(), io::Error> {
+        self.write_rows(writer, &mut |_, _| {})
+    }
+
+    fn write_rows<W: Write, F: FnMut(usize, usize)>(
+        &self,
+        writer: &mut W,
+        progress: &mut F,
+    ) -> Result<(), io::Error> {
+        const PROGRESS_INTERVAL: usize = 4096;
+        let line_ending = self.line_ending.as_str().as_bytes();
+        let total = self.rows.len();
+        for (index, row) in self.rows.iter().enumerate() {
+            writer.write_all(row.as_bytes())?;
+            if self.ensure_final_newline || index + 1 < total {
+                writer.write_all(line_ending)?;
+            }
+            if (index + 1) % PROGRESS_INTERVAL == 0 && index + 1 < total {
+                progress(index + 1, total);
+            }
+        }
+        progress(total, total);
+        Ok(())
+    }
+}
+
+/// Why `Document` refused an edit.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EditError {
+    /// The position lies below the line just past the end of the document.
+    OutOfBounds(Position),
+}
+
+impl fmt::Display for EditError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::OutOfBounds(at) => write!(f, "position {}:{} is out of bounds", at.y, at.x),
+        }
+    }
+}
+
+impl std::error::Error for EditError {}
+
+/// Formats the document exactly as `write_to` would write it, which also
+/// provides `to_string`.
+impl fmt::Display for Document {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (index, row) in self.rows.iter().enumerate() {
+            f.write_str(row.as_str())?;
+            if self.ensure_final_newline || index + 1 < self.rows.len() {
+                f.write_str(self.line_ending.as_str())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<'a> IntoIterator for &'a Document {
+    type Item = &'a Row;
+    type IntoIter = std::slice::Iter<'a, Row>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+/// Scores a greedy subsequence match of `needle` that starts right at the
+/// beginning of `graphemes`: ten points per matched grapheme, five more for
+/// each one that directly follows the previous match, and one point off for
+/// every grapheme skipped in between.
+fn fuzzy_score(graphemes: &[String], needle: &[String]) -> Option<i64> {
+    if graphemes.first() != needle.first() {
+        return None;
+    }
+    let mut score = 10;
+    let mut last = 0;
+    let mut index = 1;
+    for grapheme in &needle[1..] {
+        let found = index + graphemes[index..].iter().position(|g| g == grapheme)?;
+        let gap = (found - last - 1) as i64;
+        score += 10 - gap + if gap == 0 { 5 } else { 0 };
+        last = found;
+        index = found + 1;
+    }
+    Some(score)
+}
+
+/// Gives `to` the permissions of `from`, if it exists, so replacing a file
+/// keeps e.g. its executable bit.
+fn copy_permissions(from: &Path, to: &Path) -> Result<(), io::Error> {
+    match fs::metadata(from) {
+        Ok(metadata) => fs::set_permissions(to, metadata.permissions()),
+        Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(error) => Err(error),
+    }
+}
+
+/// Removes every CSI sequence: `ESC [`, parameter and intermediate bytes,
+/// then one final byte. An unterminated sequence runs to the end of the line.
+fn strip_ansi(line: &str) -> String {
+    let mut result = String::with_capacity(line.len());
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' && chars.peek() == Some(&'[') {
+            chars.next();
+            for c in chars.by_ref() {
+                if ('\x40'..='\x7e').contains(&c) {
+                    break;
+                }
+            }
+        } else {
+            result.push(c);
+        }
+    }
+    result
+}
+
+fn is_blank(row: &Row, whitespace_is_blank: bool) -> bool {
+    if whitespace_is_blank {
+        row.as_str().trim().is_empty()
+    } else {
+        row.is_empty()
+    }
+}
+
+fn highlighted_rows<'a>(lines: impl Iterator<Item = &'a str>, syntax: &Syntax) -> Vec<Row> {
+    lines
+        .map(|line| {
+            let mut row = Row::from(line);
+            row.highlight(syntax, None, SearchOptions::default());
+            row
+        })
+        .collect()
+}
+
+/// Returns the hidden `.name.extension` file next to `path`.
+fn sibling_path(path: &Path, extension: &str) -> PathBuf {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    path.with_file_name(format!(".{name}.{extension}"))
+}
+
+fn swap_path(path: &Path) -> PathBuf {
+    sibling_path(path, "swp")
+}
+
+fn temp_path(path: &Path) -> PathBuf {
+    sibling_path(path, "tmp")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::highlighting::Type;
+
+    /// Writes `contents` to `name` in a temporary directory, removing any swap
+    /// file left behind by an earlier run, and returns the path.
+    fn temp_file(name: &str, contents: &str) -> String {
+        let dir = std::env::temp_dir().join(format!("ferryte-tests-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(name);
+        let _ = fs::remove_file(swap_path(&path));
+        fs::write(&path, contents).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn auto_save_skips_a_clean_buffer() {
+        let path = temp_file("auto_save_clean.txt", "hello\n");
+        let mut document = Document::open(&path).unwrap();
+        assert!(!document.auto_save().unwrap());
+        let mut unnamed = Document::default();
+        unnamed.insert(&Position::default(), 'x').unwrap();
+        assert!(!unnamed.auto_save().unwrap());
+    }
+
+    #[test]
+    fn auto_save_writes_a_dirty_buffer() {
+        let path = temp_file("auto_save_dirty.txt", "hello\n");
+        let mut document = Document::open(&path).unwrap();
+        document.insert(&Position { x: 5, y: 0 }, '!').unwrap();
+        assert!(document.auto_save().unwrap());
+        assert!(!document.is_dirty());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "hello!\n");
+        assert!(document.undo().is_some());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn save_keeps_the_file_mode() {
+        use std::os::unix::fs::PermissionsExt;
+        let path = temp_file("script.sh", "echo hi\n");
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+        let mut document = Document::open(&path).unwrap();
+        document.insert(&Position::default(), '#').unwrap();
+        document.auto_save().unwrap();
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o755);
+    }
+
+    #[test]
+    fn first_edit_writes_a_recoverable_swap_file() {
+        let path = temp_file("swap.rs", "one\ntwo\n");
+        let mut document = Document::open(&path).unwrap();
+        assert!(!Document::has_swap(&path));
+        document.insert(&Position { x: 0, y: 1 }, 'X').unwrap();
+        assert!(Document::has_swap(&path));
+        let recovered = Document::recover_from_swap(&path).unwrap();
+        assert_eq!(rows(&recovered), ["one", "Xtwo"]);
+        assert_eq!(recovered.filename(), Some(&path[..]));
+        assert!(recovered.is_dirty());
+        document.save().unwrap();
+        assert!(!Document::has_swap(&path));
+    }
+
+    fn rows(document: &Document) -> Vec<&str> {
+        document.iter().map(Row::as_str).collect()
+    }
+
+    #[test]
+    fn rows_can_be_iterated_by_reference() {
+        let document = Document::from_str("a\nb\nc\n");
+        let lines: Vec<&str> = (&document).into_iter().map(Row::as_str).collect();
+        assert_eq!(lines, ["a", "b", "c"]);
+        assert_eq!(document.iter().count(), 3);
+    }
+
+    #[test]
+    fn byte_len_matches_the_saved_file() {
+        let path = temp_file("byte_len.txt", "ab\r\ncd\r\n");
+        let mut document = Document::open(&path).unwrap();
+        assert_eq!(document.line_ending(), LineEnding::CrLf);
+        assert_eq!(document.byte_len(), 8);
+        document.set_ensure_final_newline(false);
+        assert_eq!(document.byte_len(), 6);
+        document.insert(&Position::default(), 'é').unwrap();
+        document.save().unwrap();
+        assert_eq!(fs::read(&path).unwrap().len(), document.byte_len());
+        let mut buffer = Vec::new();
+        document.write_to(&mut buffer).unwrap();
+        assert_eq!(buffer.len(), document.byte_len());
+    }
+
+    #[test]
+    fn write_to_writes_exact_bytes_and_keeps_the_buffer_dirty() {
+        let mut document = Document::from_str("ab\ncd\n");
+        document.insert(&Position { x: 0, y: 1 }, 'x').unwrap();
+        let mut buffer = Vec::new();
+        document.write_to(&mut buffer).unwrap();
+        assert_eq!(buffer, b"ab\nxcd\n");
+        assert!(document.is_dirty());
+    }
+
+    #[test]
+    fn read_from_parses_an_in_memory_buffer() {
+        let document = Document::read_from(&b"one\r\ntwo\r\nthree"[..]).unwrap();
+        assert_eq!(rows(&document), ["one", "two", "three"]);
+        assert_eq!(document.line_ending(), LineEnding::CrLf);
+        assert!(document.filename().is_none());
+    }
+
+    #[test]
+    fn shebang_picks_the_syntax_when_the_extension_does_not() {
+        let path = temp_file("script", "#!/usr/bin/env python3\nprint(1)\n");
+        assert_eq!(Document::open(&path).unwrap().syntax().name(), "Python");
+        let path = temp_file("run", "#!/bin/bash\n");
+        assert_eq!(Document::open(&path).unwrap().syntax().name(), "Shell");
+        let path = temp_file("script.rs", "#!/usr/bin/env python3\n");
+        assert_eq!(Document::open(&path).unwrap().syntax().name(), "Rust");
+    }
+
+    fn count_of(document: &Document, highlighting_type: Type) -> usize {
+        document
+            .iter()
+            .flat_map(Row::highlighting)
+            .filter(|&&t| t == highlighting_type)
+            .count()
+    }
+
+    #[test]
+    fn bracket_highlight_marks_exactly_the_pair() {
+        let mut document = Document::from_str("fn a(b) {\n  [1]\n}");
+        let open = Position { x: 8, y: 0 };
+        let close = document.matching_bracket(&open).unwrap();
+        assert_eq!(close, Position { x: 0, y: 2 });
+        assert_eq!(document.matching_bracket(&close), Some(open));
+        document.set_bracket_highlight(Some((open, close)));
+        assert_eq!(count_of(&document, Type::MatchBracket), 2);
+        assert_eq!(document.highlight_at(&open), Type::MatchBracket);
+        document.highlight(None, SearchOptions::default());
+        assert_eq!(document.highlight_at(&close), Type::MatchBracket);
+        document.set_bracket_highlight(None);
+        assert_eq!(count_of(&document, Type::MatchBracket), 0);
+    }
+
+    #[test]
+    fn snippet_placeholders_are_reported_in_stop_order() {
+        let mut document = Document::from_str("ab");
+        let (end, placeholders) = document
+            .insert_snippet(&Position { x: 1, y: 0 }, "fn $1() {\n    $0\n}")
+            .unwrap();
+        assert_eq!(rows(&document), ["afn () {", "    ", "}b"]);
+        assert_eq!(
+            placeholders,
+            [Position { x: 4, y: 0 }, Position { x: 4, y: 1 }]
+        );
+        assert_eq!(end, Position { x: 1, y: 2 });
+    }
+
+    #[test]
+    fn find_searches_the_current_row_from_past_its_end() {
+        let document = Document::from_str("foo bar foo\nx foo\nfoo");
+        let find = |x, y, direction| document.find("foo", &Position { x, y }, direction);
+        assert_eq!(
+            find(1, 0, SearchDirection::Forward),
+            Some(Position { x: 8, y: 0 })
+        );
+        assert_eq!(
+            find(50, 1, SearchDirection::Backward),
+            Some(Position { x: 2, y: 1 })
+        );
+        assert_eq!(
+            find(50, 0, SearchDirection::Forward),
+            Some(Position { x: 2, y: 1 })
+        );
+        assert_eq!(
+            find(2, 1, SearchDirection::Backward),
+            Some(Position { x: 8, y: 0 })
+        );
+        assert_eq!(
+            document.find("zzz", &Position::default(), SearchDirection::Forward),
+            None
+        );
+    }
+
+    #[test]
+    fn set_dirty_is_cleared_by_save() {
+        let path = temp_file("set_dirty.txt", "a\n");
+        let mut document = Document::open(&path).unwrap();
+        document.set_dirty(true);
+        assert!(document.is_dirty());
+        document.save().unwrap();
+        assert!(!document.is_dirty());
+        document.set_dirty(true);
+        document.set_dirty(false);
+        assert!(!document.is_dirty());
+    }
+
+    #[test]
+    fn newline_splits_a_real_row() {
+        let mut document = Document::from_str("hello");
+        assert_eq!(
+            document.insert_newline(&Position { x: 2, y: 0 }),
+            Ok(Position { x: 0, y: 1 })
+        );
+        assert_eq!(rows(&document), ["he", "llo"]);
+    }
+
+    #[test]
+    fn newline_on_the_virtual_last_line_only_appends_a_row() {
+        let mut document = Document::from_str("hello");
+        assert_eq!(
+            document.insert_newline(&Position { x: 7, y: 1 }),
+            Ok(Position { x: 0, y: 2 })
+        );
+        assert_eq!(rows(&document), ["hello", ""]);
+        let far = Position { x: 0, y: 9 };
+        assert_eq!(
+            document.insert_newline(&far),
+            Err(EditError::OutOfBounds(far))
+        );
+        assert_eq!(document.len(), 2);
+    }
+
+    #[test]
+    fn numbers_are_highlighted_only_where_the_syntax_wants_them() {
+        let text = Document::open(&temp_file("numbers.txt", "x = 42\n")).unwrap();
+        let rust = Document::open(&temp_file("numbers.rs", "x = 42\n")).unwrap();
+        assert_eq!(count_of(&text, Type::Number), 0);
+        assert_eq!(count_of(&rust, Type::Number), 2);
+    }
+
+    #[test]
+    fn plain_text_edits_leave_highlighting_empty() {
+        let path = temp_file("plain.txt", "hello (1)\n");
+        let mut document = Document::open(&path).unwrap();
+        document.insert(&Position::default(), 'x').unwrap();
+        assert!(document.row(0).unwrap().highlighting().is_empty());
+    }
+
+    #[test]
+    fn highlight_row_only_touches_that_row() {
+        let mut document = Document::from_str("foo\nfoo");
+        document.highlight_row(1, Some("foo"), SearchOptions::default());
+        assert!(document.row(0).unwrap().highlighting().is_empty());
+        assert_eq!(document.row(1).unwrap().highlighting(), [Type::Search; 3]);
+        document.highlight_row(7, Some("foo"), SearchOptions::default());
+    }
+
+    #[test]
+    fn find_match_reports_the_match_length() {
+        let document = Document::from_str("an Error here\nerror");
+        let ignore_case = SearchOptions {
+            ignore_case: true,
+            ..SearchOptions::default()
+        };
+        let forward = SearchDirection::Forward;
+        let at = Position::default();
+        assert_eq!(
+            document.find_match("error", &at, forward, ignore_case),
+            Some(Match {
+                start: Position { x: 3, y: 0 },
+                len: 5
+            })
+        );
+        assert_eq!(
+            document.find_match("error", &at, forward, SearchOptions::default()),
+            Some(Match {
+                start: Position { x: 0, y: 1 },
+                len: 5
+            })
+        );
+        let backward = document.find_match(
+            "ERROR",
+            &Position { x: 3, y: 1 },
+            SearchDirection::Backward,
+            ignore_case,
+        );
+        assert_eq!(
+            backward.map(|found| found.start),
+            Some(Position { x: 3, y: 0 })
+        );
+    }
+
+    #[test]
+    fn blank_lines_are_counted_at_both_ends() {
+        let document = Document::from_str("\n\n \nx\n\n\t\n\n\n");
+        assert_eq!(document.leading_blank_lines(false), 2);
+        assert_eq!(document.leading_blank_lines(true), 3);
+        assert_eq!(document.trailing_blank_lines(false), 2);
+        assert_eq!(document.trailing_blank_lines(true), 4);
+        let three = Document::from_str("\n\nx\n\n\n\n");
+        assert_eq!(three.leading_blank_lines(false), 2);
+        assert_eq!(three.trailing_blank_lines(false), 3);
+    }
+
+    #[test]
+    fn active_line_background_covers_only_that_row() {
+        let path = temp_file("active_line.rs", "x = 1\ny = 2\n");
+        let mut document = Document::open(&path).unwrap();
+        document.set_active_line(Some(1));
+        let spans = document.render_spans(1, 0, 10);
+        assert_eq!(spans.len(), 2);
+        assert!(spans.iter().all(|s| s.background == Some(Type::ActiveLine)));
+        assert_eq!(spans[1].highlighting, Type::Number);
+        assert!(document
+            .render_spans(0, 0, 10)
+            .iter()
+            .all(|s| s.background.is_none()));
+    }
+
+    #[test]
+    fn normalizing_on_open_is_opt_in() {
+        let path = temp_file("decomposed.txt", "cafe\u{301}\n");
+        let document = Document::open(&path).unwrap();
+        assert_eq!(rows(&document), ["cafe\u{301}"]);
+        assert!(!document.was_normalized());
+        let options = OpenOptions {
+            normalize: true,
+            ..OpenOptions::default()
+        };
+        let document = Document::open_with(&path, options).unwrap();
+        assert_eq!(rows(&document), ["café"]);
+        assert_eq!(document.row(0).unwrap().len(), 4);
+        assert!(document.was_normalized());
+    }
+
+    #[test]
+    fn max_line_width_follows_edits() {
+        let mut document = Document::from_str("ab\n\tx\nabcd");
+        assert_eq!(document.max_line_width(4), 5);
+        assert_eq!(document.max_line_width(8), 9);
+        let start = Position { x: 0, y: 2 };
+        document.insert(&start, '日').unwrap();
+        assert_eq!(document.max_line_width(8), 9);
+        document.insert(&start, '日').unwrap();
+        document.insert(&start, '日').unwrap();
+        assert_eq!(document.max_line_width(8), 10);
+        document.delete(&start).unwrap();
+        document.delete(&start).unwrap();
+        assert_eq!(document.max_line_width(8), 9);
+    }
+
+    #[test]
+    fn open_at_parses_a_line_and_column_suffix() {
+        let path = temp_file("open_at.rs", "a\nb\nc\nd\nhello\n");
+        let (document, position) = Document::open_at(&format!("{path}:5:3")).unwrap();
+        assert_eq!(document.len(), 5);
+        assert_eq!(position, Position { x: 2, y: 4 });
+        assert_eq!(Document::open_at(&path).unwrap().1, Position::default());
+        assert_eq!(
+            Document::open_at(&format!("{path}:2")).unwrap().1,
+            Position { x: 0, y: 1 }
+        );
+        assert_eq!(
+            Document::open_at(&format!("{path}:99:99")).unwrap().1,
+            Position { x: 5, y: 4 }
+        );
+        assert!(Document::open_at(&format!("{path}:x")).is_err());
+    }
+
+    #[test]
+    fn transactions_undo_as_one_step_and_nest() {
+        let mut document = Document::from_str("ab\ncd");
+        document.insert(&Position { x: 0, y: 0 }, 'x').unwrap();
+        document.begin_transaction();
+        document.insert(&Position { x: 0, y: 1 }, '1').unwrap();
+        document.begin_transaction();
+        document.insert(&Position { x: 1, y: 1 }, '2').unwrap();
+        document.commit_transaction();
+        document.insert(&Position { x: 2, y: 1 }, '\n').unwrap();
+        document.commit_transaction();
+        assert_eq!(rows(&document), ["xab", "12", "cd"]);
+        assert_eq!(document.undo(), Some(Position { x: 0, y: 1 }));
+        assert_eq!(rows(&document), ["xab", "cd"]);
+        document.delete(&Position { x: 3, y: 0 }).unwrap();
+        assert_eq!(rows(&document), ["xabcd"]);
+        document.undo();
+        assert_eq!(rows(&document), ["xab", "cd"]);
+        document.undo();
+        assert_eq!(rows(&document), ["ab", "cd"]);
+        assert_eq!(document.undo(), None);
+    }
+
+    #[test]
+    fn undo_reverts_appended_rows_and_snippets() {
+        let mut document = Document::from_str("ab\ncd");
+        document.insert(&Position { x: 0, y: 2 }, 'z').unwrap();
+        document.insert_newline(&Position { x: 0, y: 3 }).unwrap();
+        assert_eq!(document.len(), 4);
+        document.undo();
+        document.undo();
+        assert_eq!(rows(&document), ["ab", "cd"]);
+        document
+            .insert_snippet(&Position { x: 0, y: 0 }, "a$1\nb")
+            .unwrap();
+        document.undo();
+        assert_eq!(rows(&document), ["ab", "cd"]);
+    }
+
+    #[test]
+    fn highlight_at_reports_the_type_under_the_cursor() {
+        let path = temp_file("highlight_at.rs", "x\n");
+        let mut document = Document::open(&path).unwrap();
+        document.insert(&Position { x: 1, y: 0 }, '7').unwrap();
+        assert_eq!(document.highlight_at(&Position { x: 1, y: 0 }), Type::None);
+        document.insert(&Position { x: 1, y: 0 }, ' ').unwrap();
+        assert_eq!(
+            document.highlight_at(&Position { x: 2, y: 0 }),
+            Type::Number
+        );
+        assert_eq!(document.highlight_at(&Position { x: 9, y: 9 }), Type::None);
+    }
+
+    #[test]
+    fn backspace_joins_rows_only_at_the_start_of_a_line() {
+        let mut document = Document::from_str("ab\ncd");
+        let start = Position { x: 0, y: 0 };
+        assert_eq!(document.backspace(&start), Ok(start));
+        assert_eq!(rows(&document), ["ab", "cd"]);
+        assert_eq!(
+            document.backspace(&Position { x: 0, y: 1 }),
+            Ok(Position { x: 2, y: 0 })
+        );
+        assert_eq!(rows(&document), ["abcd"]);
+        assert_eq!(
+            document.backspace(&Position { x: 2, y: 0 }),
+            Ok(Position { x: 1, y: 0 })
+        );
+        assert_eq!(rows(&document), ["acd"]);
+        assert_eq!(
+            document.backspace(&Position { x: 0, y: 1 }),
+            Ok(Position { x: 3, y: 0 })
+        );
+        assert_eq!(rows(&document), ["acd"]);
+    }
+
+    #[test]
+    fn backspace_past_the_end_of_a_row_deletes_its_last_grapheme() {
+        let mut document = Document::from_str("abc\ndef");
+        assert_eq!(
+            document.backspace(&Position { x: 10, y: 0 }),
+            Ok(Position { x: 2, y: 0 })
+        );
+        assert_eq!(rows(&document), ["ab", "def"]);
+    }
+
+    #[test]
+    fn from_str_builds_an_unnamed_clean_document() {
+        let document = Document::from_str("a\nb\n");
+        assert_eq!(document.len(), 2);
+        assert!(!document.is_dirty());
+        assert!(document.filename().is_none());
+        let path = temp_file("from_str.txt", "a\nb\n");
+        assert_eq!(Document::open(&path).unwrap().len(), document.len());
+        assert_eq!(Document::from_str("").len(), 0);
+        assert_eq!(Document::from_str("x\r\ny").line_ending(), LineEnding::CrLf);
+    }
+
+    #[test]
+    fn display_matches_the_written_bytes() {
+        for text in ["a\nb\n", "", "x\r\ny\r\n"] {
+            assert_eq!(Document::from_str(text).to_string(), text);
+        }
+        let mut document = Document::from_str("a\nb");
+        document.set_ensure_final_newline(false);
+        let mut written = Vec::new();
+        document.write_to(&mut written).unwrap();
+        assert_eq!(document.to_string().into_bytes(), written);
+    }
+
+    #[test]
+    fn trim_row_end_removes_trailing_whitespace_as_an_edit() {
+        let mut document = Document::from_str("let x = 1;  \t\nclean\n");
+        assert!(!document.trim_row_end(1));
+        assert!(!document.is_dirty());
+        assert!(!document.trim_row_end(5));
+        assert!(document.trim_row_end(0));
+        assert!(document.is_dirty());
+        assert_eq!(rows(&document), ["let x = 1;", "clean"]);
+        document.undo();
+        assert_eq!(rows(&document), ["let x = 1;  \t", "clean"]);
+    }
+
+    #[test]
+    fn trim_row_end_leaves_the_active_line_alone() {
+        let mut document = Document::from_str("a  \nb  \n");
+        document.set_active_line(Some(0));
+        assert!(!document.trim_row_end(0));
+        assert!(!document.is_dirty());
+        assert!(document.trim_row_end(1));
+        assert_eq!(rows(&document), ["a  ", "b"]);
+    }
+
+    #[test]
+    fn edits_below_the_virtual_last_line_are_rejected() {
+        let mut document = Document::from_str("ab\n");
+        let far = Position { x: 0, y: 5 };
+        assert_eq!(document.insert(&far, 'x'), Err(EditError::OutOfBounds(far)));
+        assert!(document.delete(&far).is_err());
+        assert!(document.backspace(&far).is_err());
+        assert!(document.insert_snippet(&far, "a$1").is_err());
+        assert!(!document.is_dirty());
+        assert_eq!(document.delete(&Position { x: 0, y: 1 }), Ok(()));
+        assert_eq!(document.to_string(), "ab\n");
+    }
+
+    #[test]
+    fn visual_line_count_sums_wrapped_rows() {
+        let document = Document::from_str("short\n\nabcdefghij\nabcd\n");
+        assert_eq!(document.visual_line_count(4, 4), 2 + 1 + 3 + 1);
+        assert_eq!(Document::default().visual_line_count(4, 4), 0);
+    }
+
+    #[test]
+    fn visual_to_logical_finds_the_wrapped_segment() {
+        let document = Document::from_str("abcdefghij\nxy\n");
+        assert_eq!(document.visual_to_logical(0, 4, 4), Some((0, 0)));
+        assert_eq!(document.visual_to_logical(2, 4, 4), Some((0, 2)));
+        assert_eq!(document.visual_to_logical(3, 4, 4), Some((1, 0)));
+        assert_eq!(document.visual_to_logical(4, 4, 4), None);
+    }
+
+    #[test]
+    fn fuzzy_find_ranks_tighter_matches_first() {
+        let document = Document::from_str("f_o_o_b_a_r\nnothing\nxx foobar\n");
+        let results = document.fuzzy_find("FooBar");
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].0, Position { x: 3, y: 2 });
+        assert_eq!(results[1].0, Position { x: 0, y: 0 });
+        assert!(results[0].1 > results[1].1);
+        assert!(document.fuzzy_find("").is_empty());
+        assert!(document.fuzzy_find("zz").is_empty());
+    }
+
+    #[test]
+    fn newline_continues_line_comments_when_enabled() {
+        let mut document = Document::from_str("    // note here\nlet x;\n");
+        document.set_syntax(Syntax::from_filename("a.rs"));
+        assert_eq!(
+            document.insert_newline(&Position { x: 11, y: 0 }),
+            Ok(Position { x: 0, y: 1 })
+        );
+        document.undo();
+        document.set_continue_comments(true);
+        assert_eq!(
+            document.insert_newline(&Position { x: 12, y: 0 }),
+            Ok(Position { x: 7, y: 1 })
+        );
+        assert_eq!(rows(&document), ["    // note ", "    // here", "let x;"]);
+        assert_eq!(
+            document.insert_newline(&Position { x: 4, y: 2 }),
+            Ok(Position { x: 0, y: 3 })
+        );
+        assert_eq!(rows(&document)[3], "x;");
+        assert_eq!(
+            document.insert_newline(&Position { x: 2, y: 0 }),
+            Ok(Position { x: 0, y: 1 })
+        );
+        assert_eq!(rows(&document)[1], "  // note ");
+    }
+
+    #[test]
+    fn snippet_placeholders_follow_a_continued_comment() {
+        let mut document = Document::from_str("// hi");
+        document.set_syntax(Syntax::from_filename("a.rs"));
+        document.set_continue_comments(true);
+        let (end, placeholders) = document
+            .insert_snippet(&Position { x: 5, y: 0 }, "\nab$0")
+            .unwrap();
+        assert_eq!(rows(&document), ["// hi", "// ab"]);
+        assert_eq!(end, Position { x: 5, y: 1 });
+        assert_eq!(placeholders, [Position { x: 5, y: 1 }]);
+    }
+
+    #[test]
+    fn deleting_at_the_end_of_the_document_is_not_an_edit() {
+        let mut document = Document::from_str("ab\ncd");
+        document.delete(&Position { x: 2, y: 1 }).unwrap();
+        document.delete(&Position { x: 9, y: 1 }).unwrap();
+        assert!(!document.is_dirty());
+        assert_eq!(document.undo(), None);
+        document.delete(&Position { x: 5, y: 0 }).unwrap();
+        assert_eq!(document.to_string(), "abcd\n");
+    }
+
+    #[test]
+    fn typing_a_block_comment_opener_closes_it() {
+        let mut document = Document::from_str("x\n");
+        document.set_syntax(Syntax::from_filename("a.c"));
+        let at = document.type_char(&Position { x: 1, y: 0 }, '/').unwrap();
+        let at = document.type_char(&at, '*').unwrap();
+        assert_eq!(at, Position { x: 3, y: 0 });
+        assert_eq!(document.to_string(), "x/* */\n");
+        document.undo();
+        assert_eq!(document.to_string(), "x/\n");
+        let mut plain = Document::from_str("");
+        let at = plain.type_char(&Position::default(), '/').unwrap();
+        plain.type_char(&at, '*').unwrap();
+        assert_eq!(plain.to_string(), "/*\n");
+    }
+
+    #[test]
+    fn matches_and_find_all_agree_with_find() {
+        let document = Document::from_str("aXa\n\nxa aa\n");
+        let options = SearchOptions {
+            ignore_case: true,
+            ..SearchOptions::default()
+        };
+        let all = document.find_all("a", options);
+        assert_eq!(all.len(), 5);
+        assert_eq!(document.matches("a", options).collect::<Vec<_>>(), all);
+        assert_eq!(
+            document.matches("xa", options).nth(1),
+            Some(Position { x: 0, y: 2 })
+        );
+        assert_eq!(document.matches("", options).count(), 0);
+        let document = Document::from_str("e\u{301}x ex\n");
+        let found = Position { x: 3, y: 0 };
+        assert_eq!(
+            document.find("e", &Position::default(), SearchDirection::Forward),
+            Some(found)
+        );
+        assert_eq!(document.find_all("e", SearchOptions::default()), [found]);
+    }
+
+    #[test]
+    fn content_hash_covers_text_and_line_endings_only() {
+        let plain = Document::from_str("ab\ncd\n");
+        let mut rust = Document::from_str("ab\ncd\n");
+        rust.set_syntax(Syntax::from_filename("a.rs"));
+        assert_eq!(plain.content_hash(), rust.content_hash());
+        assert_ne!(
+            plain.content_hash(),
+            Document::from_str("abcd\n").content_hash()
+        );
+        assert_ne!(
+            plain.content_hash(),
+            Document::from_str("ab\r\ncd\r\n").content_hash()
+        );
+        rust.insert(&Position { x: 0, y: 0 }, 'x').unwrap();
+        assert_ne!(plain.content_hash(), rust.content_hash());
+        assert_eq!(Document::default().content_hash(), 0xcbf2_9ce4_8422_2325);
+    }
+
+    #[test]
+    fn grapheme_at_returns_the_whole_cluster() {
+        let document = Document::from_str("a日本e\u{301}\n");
+        assert_eq!(document.grapheme_at(&Position { x: 1, y: 0 }), Some("日"));
+        assert_eq!(
+            document.grapheme_at(&Position { x: 3, y: 0 }),
+            Some("e\u{301}")
+        );
+        assert_eq!(document.grapheme_at(&Position { x: 4, y: 0 }), None);
+        assert_eq!(document.grapheme_at(&Position { x: 0, y: 1 }), None);
+    }
+
+    #[test]
+    fn max_undo_caps_the_undo_history() {
+        let mut document = Document::from_str("");
+        assert_eq!(document.max_undo(), 1000);
+        document.set_max_undo(3);
+        for x in 0..5 {
+            document.insert(&Position { x, y: 0 }, 'a').unwrap();
+        }
+        let mut undos = 0;
+        while document.undo().is_some() {
+            undos += 1;
+        }
+        assert_eq!(undos, 3);
+        assert_eq!(document.to_string(), "aa\n");
+        document.insert(&Position::default(), 'b').unwrap();
+        document.insert(&Position::default(), 'b').unwrap();
+        document.set_max_undo(1);
+        assert!(document.undo().is_some());
+        assert!(document.undo().is_none());
+        document.set_max_undo(0);
+        document.insert(&Position::default(), 'c').unwrap();
+        document.type_char(&Position::default(), 'c').unwrap();
+        assert!(document.undo().is_none());
+    }
+
+    #[test]
+    fn dirty_rows_lists_only_rows_changed_since_drawn() {
+        let mut document = Document::from_str("a\nb\nc\nd\n");
+        assert_eq!(document.dirty_rows(), [0, 1, 2, 3]);
+        for y in 0..4 {
+            document.mark_drawn(y);
+        }
+        assert!(document.dirty_rows().is_empty());
+        document.insert(&Position { x: 1, y: 1 }, 'x').unwrap();
+        assert_eq!(document.dirty_rows(), [1]);
+        document.mark_drawn(1);
+        document.delete(&Position { x: 2, y: 1 }).unwrap();
+        assert_eq!(document.dirty_rows(), [1, 2]);
+        document.mark_drawn(1);
+        document.mark_drawn(2);
+        document.set_active_line(Some(0));
+        assert_eq!(document.dirty_rows(), [0]);
+        document.mark_drawn(0);
+        document.set_active_line(Some(0));
+        assert!(document.dirty_rows().is_empty());
+    }
+
+    #[test]
+    fn highlight_terms_gives_each_term_its_own_type() {
+        let mut document = Document::from_str("ERROR at WARN: error\n");
+        let terms = vec![
+            ("error".to_string(), Type::Search),
+            ("warn".to_string(), Type::Todo),
+            ("or at".to_string(), Type::Comment),
+        ];
+        let options = SearchOptions {
+            ignore_case: true,
+            ..SearchOptions::default()
+        };
+        document.highlight_terms(&terms, options);
+        let highlighting = document.row(0).unwrap().highlighting();
+        assert_eq!(highlighting[..3], [Type::Search; 3]);
+        assert_eq!(highlighting[3..8], [Type::Comment; 5]);
+        assert_eq!(highlighting[9..13], [Type::Todo; 4]);
+        assert_eq!(highlighting[15..20], [Type::Search; 5]);
+    }
+
+    #[test]
+    fn truncate_to_last_keeps_the_tail_as_one_edit() {
+        let text: String = (0..1000).map(|i| format!("line {i}\n")).collect();
+        let mut document = Document::from_str(&text);
+        document.truncate_to_last(2000);
+        assert!(!document.is_dirty());
+        document.truncate_to_last(100);
+        assert_eq!(document.len(), 100);
+        assert_eq!(rows(&document)[0], "line 900");
+        assert_eq!(rows(&document)[99], "line 999");
+        assert!(document.is_dirty());
+        document.undo();
+        assert_eq!(document.to_string(), text);
+    }
+
+    #[test]
+    fn append_str_adds_rows_at_the_end() {
+        let mut document = Document::from_str("a\n");
+        document.append_str("b\r\nc\n");
+        assert_eq!(document.len(), 3);
+        assert_eq!(document.to_string(), "a\nb\nc\n");
+        assert!(document.is_dirty());
+        document.append_str("d");
+        assert_eq!(document.len(), 4);
+        document.undo();
+        assert_eq!(document.len(), 3);
+        document.append_str("");
+        assert_eq!(document.len(), 3);
+    }
+
+    #[test]
+    fn the_codepoint_cursor_model_edits_combining_marks() {
+        let mut document = Document::from_str("e\u{301}x\n");
+        document.set_cursor_model(CursorModel::Codepoint);
+        document.delete(&Position { x: 1, y: 0 }).unwrap();
+        assert_eq!(document.to_string(), "ex\n");
+        document
+            .insert(&Position { x: 1, y: 0 }, '\u{300}')
+            .unwrap();
+        assert_eq!(document.to_string(), "e\u{300}x\n");
+        let found = document.find_match(
+            "x",
+            &Position::default(),
+            SearchDirection::Forward,
+            SearchOptions::default(),
+        );
+        let start = Position { x: 2, y: 0 };
+        assert_eq!(found, Some(Match { start, len: 1 }));
+        assert_eq!(
+            document.backspace(&Position { x: 0, y: 1 }),
+            Ok(Position { x: 3, y: 0 })
+        );
+        assert_eq!(
+            document.insert_newline(&Position { x: 2, y: 0 }),
+            Ok(Position { x: 0, y: 1 })
+        );
+        assert_eq!(document.to_string(), "e\u{300}\nx\n");
+        document.set_cursor_model(CursorModel::Grapheme);
+        assert_eq!(
+            document.backspace(&Position { x: 0, y: 1 }),
+            Ok(Position { x: 1, y: 0 })
+        );
+    }
+
+    #[test]
+    fn utf16_positions_count_surrogate_pairs() {
+        let document = Document::from_str("a😀b\n");
+        assert_eq!(document.to_utf16_position(&Position { x: 1, y: 0 }), (0, 1));
+        assert_eq!(document.to_utf16_position(&Position { x: 2, y: 0 }), (0, 3));
+        assert_eq!(document.to_utf16_position(&Position { x: 3, y: 0 }), (0, 4));
+        assert_eq!(document.from_utf16_position(0, 3), Position { x: 2, y: 0 });
+        assert_eq!(document.from_utf16_position(0, 2), Position { x: 1, y: 0 });
+        assert_eq!(document.from_utf16_position(0, 9), Position { x: 3, y: 0 });
+    }
+
+    #[test]
+    fn context_at_tells_strings_comments_and_code_apart() {
+        let mut document = Document::from_str("let s = \"a \\\" // b\"; // c \"d\"\n");
+        document.set_syntax(Syntax::from_filename("a.rs"));
+        let at = |x| Position { x, y: 0 };
+        assert_eq!(document.context_at(&at(0)), SyntaxContext::Code);
+        assert_eq!(document.context_at(&at(8)), SyntaxContext::String);
+        assert_eq!(document.context_at(&at(14)), SyntaxContext::String);
+        assert_eq!(document.context_at(&at(18)), SyntaxContext::String);
+        assert_eq!(document.context_at(&at(19)), SyntaxContext::Code);
+        assert_eq!(document.context_at(&at(25)), SyntaxContext::Comment);
+        assert_eq!(document.context_at(&at(27)), SyntaxContext::Comment);
+        let plain = Document::from_str("\"x\"\n");
+        assert_eq!(plain.context_at(&at(1)), SyntaxContext::Code);
+    }
+
+    #[test]
+    fn append_document_keeps_the_filename_and_rehighlights() {
+        let path = temp_file("append_document.rs", "");
+        let mut document = Document::from_str("// a\nb\nc 1\n");
+        document.set_syntax(Syntax::from_filename("a.rs"));
+        document.set_filename(Some(path.clone()));
+        document.append_document(Document::from_str("// d\n2\n"));
+        assert_eq!(document.len(), 5);
+        assert_eq!(document.filename(), Some(&path[..]));
+        assert!(document.is_dirty());
+        assert_eq!(document.row(3).unwrap().highlighting()[0], Type::Comment);
+        assert_eq!(document.row(4).unwrap().highlighting()[0], Type::Number);
+        document.remove_swap().unwrap();
+    }
+
+    #[test]
+    fn matches_on_the_active_line_can_be_hidden() {
+        let mut document = Document::from_str("foo\nfoo\n");
+        document.set_active_line(Some(1));
+        document.highlight(Some("foo"), SearchOptions::default());
+        assert_eq!(
+            document.highlight_at(&Position { x: 0, y: 1 }),
+            Type::Search
+        );
+        document.set_hide_active_line_matches(true);
+        document.highlight(Some("foo"), SearchOptions::default());
+        assert_eq!(
+            document.highlight_at(&Position { x: 0, y: 0 }),
+            Type::Search
+        );
+        assert_eq!(document.highlight_at(&Position { x: 0, y: 1 }), Type::None);
+    }
+
+    #[test]
+    fn todos_lists_keywords_in_comments_only() {
+        let mut document = Document::from_str(
+            "// TODO: first\nlet s = \"TODO not this\"; // FIXME later \nlet todo = 1;\n",
+        );
+        document.set_syntax(Syntax::from_filename("a.rs"));
+        let todos = document.todos();
+        assert_eq!(todos.len(), 2);
+        assert_eq!(
+            todos[0],
+            (Position { x: 3, y: 0 }, "TODO: first".to_string())
+        );
+        assert_eq!(
+            todos[1],
+            (Position { x: 28, y: 1 }, "FIXME later".to_string())
+        );
+    }
+
+    #[test]
+    fn word_chars_decide_what_a_word_is() {
+        let mut document = Document::from_str("foo_bar baz\n  qux\n");
+        let at = Position { x: 1, y: 0 };
+        let options = SearchOptions::default();
+        assert_eq!(document.word_at(&at).map(|m| m.len), Some(7));
+        assert_eq!(document.next_word_boundary(&at), Position { x: 8, y: 0 });
+        assert_eq!(
+            document.next_word_boundary(&Position { x: 8, y: 0 }),
+            Position { x: 2, y: 1 }
+        );
+        assert_eq!(
+            document.next_word_boundary(&Position { x: 2, y: 1 }),
+            Position { x: 5, y: 1 }
+        );
+        let start = Position::default();
+        assert!(document
+            .find_whole_word("bar", &start, SearchDirection::Forward, options)
+            .is_none());
+        document.set_word_chars("");
+        assert_eq!(document.word_at(&at).map(|m| m.len), Some(3));
+        assert_eq!(document.next_word_boundary(&at), Position { x: 4, y: 0 });
+        let found = document.find_whole_word("bar", &start, SearchDirection::Forward, options);
+        assert_eq!(found.map(|m| m.start), Some(Position { x: 4, y: 0 }));
+        let end = Position { x: 9, y: 1 };
+        assert!(document
+            .find_whole_word("ba", &end, SearchDirection::Backward, options)
+            .is_none());
+        assert_eq!(document.word_at(&Position { x: 3, y: 0 }), None);
+    }
+
+    #[test]
+    fn save_with_progress_reports_every_chunk() {
+        let path = temp_file("progress.txt", "");
+        let text: String = (0..10000).map(|i| format!("{i}\n")).collect();
+        let mut document = Document::from_str(&text);
+        document.set_filename(Some(path.clone()));
+        let mut calls = Vec::new();
+        document
+            .save_with_progress(|done, total| calls.push((done, total)))
+            .unwrap();
+        assert_eq!(calls, [(4096, 10000), (8192, 10000), (10000, 10000)]);
+        assert_eq!(fs::read_to_string(&path).unwrap(), text);
+    }
+
+    #[test]
+    fn set_filename_does_not_dirty_the_buffer() {
+        let path = temp_file("renamed.txt", "");
+        let mut document = Document::from_str("a\n");
+        assert_eq!(document.filename(), None);
+        document.set_filename(Some(path.clone()));
+        assert_eq!(document.filename(), Some(&path[..]));
+        assert!(!document.is_dirty());
+        document.set_filename(None);
+        assert_eq!(document.filename(), None);
+    }
+
+    #[test]
+    fn set_filename_moves_the_swap_file() {
+        let old = temp_file("swap_old.txt", "a\n");
+        let new = temp_file("swap_new.txt", "");
+        let mut document = Document::open(&old).unwrap();
+        document.insert(&Position::default(), 'x').unwrap();
+        assert!(Document::has_swap(&old));
+        document.set_filename(Some(new.clone()));
+        assert!(!Document::has_swap(&old));
+        assert!(Document::has_swap(&new));
+        document.save().unwrap();
+        assert!(!Document::has_swap(&new));
+        assert_eq!(fs::read_to_string(&new).unwrap(), "xa\n");
+    }
+
+    #[test]
+    fn open_reads_modelines_near_either_end() {
+        let path = temp_file("modeline.conf", "// vim: set ft=rust:\nfn main() {}\n");
+        assert_eq!(Document::open(&path).unwrap().syntax().name(), "Rust");
+        let path = temp_file(
+            "modeline_last.txt",
+            "a\nb\nc\nd\ne\nf\ng\n# -*- mode: python -*-\n",
+        );
+        assert_eq!(Document::open(&path).unwrap().syntax().name(), "Python");
+        let path = temp_file("modeline_ignored.rs", "# vim ft=python\nsavim: ft=python\n");
+        assert_eq!(Document::open(&path).unwrap().syntax().name(), "Rust");
+    }
+
+    #[test]
+    fn blank_rows_take_the_nearest_indent_depth() {
+        let document = Document::from_str("fn a() {\n\tif x {\n\t    y();\n\n\t}\n  \n}\n\nz\n");
+        assert_eq!(document.indent_depths(4), [0, 4, 8, 8, 4, 4, 0, 0, 0]);
+        assert_eq!(Document::from_str("\n\n").indent_depths(4), [0, 0]);
+        assert_eq!(Document::from_str("\n  a\n").indent_depths(4), [2, 2]);
+    }
+
+    #[test]
+    fn detect_indent_picks_the_most_common_step() {
+        let two = Document::from_str("a:\n  b:\n    c: 1\n    d: 2\n  e: 3\nf:\n  g: 4\n");
+        assert_eq!(two.detect_indent(), (IndentKind::Spaces, 2));
+        let four = Document::from_str("fn a() {\n    if x {\n        y();\n    }\n\n    z();\n}\n");
+        assert_eq!(four.detect_indent(), (IndentKind::Spaces, 4));
+        let tabs = Document::from_str("a {\n\tb\n\t\tc\n}\n");
+        assert_eq!(tabs.detect_indent(), (IndentKind::Tabs, 4));
+        let default = (IndentKind::Spaces, DEFAULT_INDENT_WIDTH);
+        assert_eq!(Document::from_str("").detect_indent(), default);
+        assert_eq!(Document::from_str("a\nb\n").detect_indent(), default);
+    }
+
+    #[test]
+    fn strip_ansi_removes_escape_sequences_on_open() {
+        let path = temp_file(
+            "colored.log",
+            "\x1b[31merror\x1b[0m: bad\n\x1b[1;32mok\x1b[m\nplain \x1b x\n",
+        );
+        let options = OpenOptions {
+            strip_ansi: true,
+            ..OpenOptions::default()
+        };
+        let document = Document::open_with(&path, options).unwrap();
+        assert_eq!(document.to_string(), "error: bad\nok\nplain \x1b x\n");
+        let document = Document::open(&path).unwrap();
+        assert!(document.to_string().contains("\x1b[31m"));
+    }
+
+    #[test]
+    fn skip_overlapping_drops_matches_inside_earlier_ones() {
+        let document = Document::from_str("aaa\nxaa\n");
+        let skip = SearchOptions {
+            skip_overlapping: true,
+            ..SearchOptions::default()
+        };
+        assert_eq!(document.find_all("aa", skip).len(), 2);
+        assert_eq!(document.find_all("aa", SearchOptions::default()).len(), 3);
+    }
+
+    #[test]
+    fn dim_unmatched_greys_out_rows_without_a_match() {
+        let mut document = Document::from_str("error: x\ninfo: y\n");
+        document.set_dim_unmatched(true);
+        document.highlight(Some("error"), SearchOptions::default());
+        let second = document.row(1).unwrap().highlighting();
+        assert_eq!(second, [Type::Dimmed; 7]);
+        let first = document.row(0).unwrap().highlighting();
+        assert_eq!(first[0], Type::Search);
+        assert!(!first.contains(&Type::Dimmed));
+        document.highlight(None, SearchOptions::default());
+        assert!(!document
+            .row(1)
+            .unwrap()
+            .highlighting()
+            .contains(&Type::Dimmed));
+    }
+
+    #[test]
+    fn filtered_rows_lists_matching_row_indices() {
+        let document = Document::from_str("foo\nbar\nFoo bar\nbaz foo\n");
+        assert_eq!(
+            document.filtered_rows("foo", SearchOptions::default()),
+            [0, 3]
+        );
+        let ignore_case = SearchOptions {
+            ignore_case: true,
+            ..SearchOptions::default()
+        };
+        assert_eq!(document.filtered_rows("foo", ignore_case), [0, 2, 3]);
+        assert!(document.filtered_rows("", ignore_case).is_empty());
+    }
+
+    #[test]
+    fn save_skips_a_clean_buffer_unless_forced_or_renamed() {
+        use std::time::{Duration, SystemTime};
+        let path = temp_file("clean_save.txt", "a\n");
+        let old = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000);
+        let backdate = || {
+            let file = fs::File::options().write(true).open(&path).unwrap();
+            file.set_modified(old).unwrap();
+        };
+        let modified = || fs::metadata(&path).unwrap().modified().unwrap();
+        backdate();
+        let mut document = Document::open(&path).unwrap();
+        document.save().unwrap();
+        assert_eq!(modified(), old);
+        document.force_save().unwrap();
+        assert_ne!(modified(), old);
+        backdate();
+        document.insert(&Position::default(), 'x').unwrap();
+        document.save().unwrap();
+        assert_ne!(modified(), old);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "xa\n");
+        let new = temp_file("clean_save_as.txt", "");
+        document.set_filename(Some(new.clone()));
+        document.save().unwrap();
+        assert_eq!(fs::read_to_string(&new).unwrap(), "xa\n");
+    }
+
+    #[test]
+    fn context_around_is_clamped_to_the_document() {
+        let document = Document::from_str("0\n1\n2\n3\n4\n5\n6\n");
+        let lines = |at, before, after| {
+            document
+                .context_around(&Position { x: 0, y: at }, before, after)
+                .into_iter()
+                .map(|(y, _)| y)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(lines(3, 2, 2), [1, 2, 3, 4, 5]);
+        assert_eq!(lines(0, 2, 1), [0, 1]);
+        assert_eq!(lines(6, 1, 5), [5, 6]);
+        assert_eq!(
+            document.context_around(&Position { x: 0, y: 2 }, 0, 0),
+            [(2, "2".to_string())]
+        );
+        assert!(Document::default()
+            .context_around(&Position::default(), 1, 1)
+            .is_empty());
+    }
+
+    #[test]
+    fn delete_block_removes_a_column_range_from_each_row() {
+        let mut document = Document::from_str("abcdefg\nab\nabcd\nabcdefgh\n");
+        document.delete_block(&Position { x: 5, y: 3 }, &Position { x: 2, y: 0 });
+        assert_eq!(rows(&document), ["abfg", "ab", "ab", "abfgh"]);
+        assert!(document.is_dirty());
+        document.undo();
+        assert_eq!(rows(&document), ["abcdefg", "ab", "abcd", "abcdefgh"]);
+        let mut short = Document::from_str("a\nb\n");
+        short.delete_block(&Position { x: 2, y: 0 }, &Position { x: 4, y: 9 });
+        assert!(!short.is_dirty());
+    }
+
+    #[test]
+    fn byte_positions_convert_both_ways() {
+        let document = Document::from_str("x\naé日\n");
+        assert_eq!(document.to_byte_position(&Position { x: 2, y: 1 }), (1, 3));
+        assert_eq!(document.from_byte_position(1, 4), Position { x: 2, y: 1 });
+        assert_eq!(document.from_byte_position(5, 4), Position { x: 0, y: 5 });
+    }
+
+    #[test]
+    fn search_results_carry_the_line_and_match_length() {
+        let document = Document::from_str("let été = 1;\nnone\nÉté été\n");
+        let options = SearchOptions {
+            ignore_case: true,
+            ..SearchOptions::default()
+        };
+        let results = document.search_results("été", options);
+        assert_eq!(results.len(), 3);
+        assert_eq!(results[0].position, Position { x: 4, y: 0 });
+        assert_eq!(results[0].line, "let été = 1;");
+        assert_eq!(results[2].position, Position { x: 4, y: 2 });
+        assert_eq!(results[2].line, "Été été");
+        assert!(results.iter().all(|result| result.match_len == 3));
+    }
+
+    #[test]
+    fn visual_positions_round_trip_through_wrapping() {
+        let document = Document::from_str("short\nabcdefghijklmnopqrstuvwxyz\nend\n");
+        for x in 0..=26 {
+            let at = Position { x, y: 1 };
+            let (line, column) = document.logical_to_visual(&at, 10, 4);
+            assert_eq!(line, 1 + x / 10);
+            assert_eq!(document.visual_to_position(line, column, 10, 4), Some(at));
+        }
+        assert_eq!(
+            document.logical_to_visual(&Position { x: 12, y: 1 }, 10, 4),
+            (2, 2)
+        );
+        assert_eq!(
+            document.logical_to_visual(&Position { x: 1, y: 2 }, 10, 4),
+            (4, 1)
+        );
+        assert_eq!(
+            document.visual_to_position(1, 50, 10, 4),
+            Some(Position { x: 9, y: 1 })
+        );
+        assert_eq!(
+            document.visual_to_position(3, 50, 10, 4),
+            Some(Position { x: 26, y: 1 })
+        );
+        assert_eq!(document.visual_to_position(9, 0, 10, 4), None);
+        let tabs = Document::from_str("\tab\tcdefgh\n");
+        for x in 0..=10 {
+            let at = Position { x, y: 0 };
+            let (line, column) = tabs.logical_to_visual(&at, 6, 4);
+            assert_eq!(tabs.visual_to_position(line, column, 6, 4), Some(at), "{x}");
+        }
+    }
+
+    #[test]
+    fn insert_str_can_trim_pasted_lines() {
+        let trim = PasteOptions {
+            trim_trailing_whitespace: true,
+            ..PasteOptions::default()
+        };
+        let mut document = Document::from_str("    let x = 1;  \n");
+        let end = document
+            .insert_str(&Position { x: 16, y: 0 }, "\nfoo();   \r\nbar(); \t", trim)
+            .unwrap();
+        assert_eq!(rows(&document), ["    let x = 1;  ", "foo();", "bar();"]);
+        assert_eq!(end, Position { x: 6, y: 2 });
+        let mut document = Document::from_str("ab\n");
+        document
+            .insert_str(&Position { x: 1, y: 0 }, "x \ny ", trim)
+            .unwrap();
+        assert_eq!(rows(&document), ["ax", "y b"]);
+    }
+
+    #[test]
+    fn insert_str_can_reindent_to_the_cursor() {
+        let options = PasteOptions {
+            trim_trailing_whitespace: true,
+            reindent: true,
+        };
+        let mut document = Document::from_str("fn a() {\n    \n}\n");
+        let end = document
+            .insert_str(
+                &Position { x: 4, y: 1 },
+                "if x {\n\t\ty(); \n\n\t}",
+                options,
+            )
+            .unwrap();
+        assert_eq!(
+            rows(&document),
+            ["fn a() {", "    if x {", "    \ty();", "", "    }", "}"]
+        );
+        assert_eq!(end, Position { x: 5, y: 4 });
+        document.undo();
+        assert_eq!(rows(&document), ["fn a() {", "    ", "}"]);
+    }
+
+    #[test]
+    fn insert_str_does_not_continue_comments() {
+        let mut document = Document::default();
+        document
+            .insert_str(&Position::default(), "a\nb", PasteOptions::default())
+            .unwrap();
+        assert_eq!(rows(&document), ["a", "b"]);
+        let mut document = Document::from_str("// c\n");
+        document.set_syntax(Syntax::from_filename("a.rs"));
+        document.set_continue_comments(true);
+        document
+            .insert_str(&Position { x: 4, y: 0 }, "\nx", PasteOptions::default())
+            .unwrap();
+        assert_eq!(rows(&document), ["// c", "x"]);
+        assert!(document.continue_comments());
+    }
+
+    #[test]
+    fn virtual_space_pads_rows_when_typing_past_the_end() {
+        let at = Position { x: 10, y: 0 };
+        let mut document = Document::from_str("abc\n");
+        assert_eq!(document.type_char(&at, 'x'), Ok(Position { x: 4, y: 0 }));
+        assert_eq!(rows(&document), ["abcx"]);
+        let mut document = Document::from_str("abc\n");
+        document.set_virtual_space(true);
+        assert!(!document.is_dirty());
+        assert_eq!(document.type_char(&at, 'x'), Ok(Position { x: 11, y: 0 }));
+        assert_eq!(rows(&document), ["abc       x"]);
+        document.undo();
+        assert_eq!(rows(&document), ["abc"]);
+        let mut document = Document::from_str("a\n");
+        document.set_syntax(Syntax::from_filename("x.rs"));
+        document.set_virtual_space(true);
+        document.type_char(&Position { x: 3, y: 0 }, '/').unwrap();
+        let end = document.type_char(&Position { x: 4, y: 0 }, '*').unwrap();
+        assert_eq!(rows(&document), ["a  /* */"]);
+        assert_eq!(end, Position { x: 5, y: 0 });
+    }
+
+    #[test]
+    fn viewport_renders_the_visible_window() {
+        let document = Document::from_str("0123456789\nabcdefghij\nxy\n\tTAB\n");
+        let view = document.viewport(1, 5, 2, 4, 4);
+        assert_eq!(view.len(), 5);
+        for (y, line) in (1..4).zip(&view) {
+            assert_eq!(*line, document.row(y).unwrap().render_cols(2, 6, 4));
+        }
+        assert!(view[0].contains("cdef") && !view[0].contains('g'));
+        assert!(view[2].contains("  TA") && !view[2].contains('B'));
+        assert_eq!(view[3..], ["", ""]);
+        let view = document.viewport(0, 2, 0, 3, 4);
+        assert!(view[0].contains("012") && view[1].contains("abc"));
+    }
+
+    fn selection(anchor: (usize, usize), cursor: (usize, usize)) -> Selection {
+        Selection {
+            anchor: Position {
+                x: anchor.0,
+                y: anchor.1,
+            },
+            cursor: Position {
+                x: cursor.0,
+                y: cursor.1,
+            },
+        }
+    }
+
+    fn caret(x: usize, y: usize) -> Selection {
+        selection((x, y), (x, y))
+    }
+
+    #[test]
+    fn type_multi_replaces_every_selection_as_one_step() {
+        let mut document = Document::from_str("foo bar foo\nbaz foo\n");
+        let mut cursors = vec![
+            selection((4, 1), (7, 1)),
+            selection((0, 0), (3, 0)),
+            selection((11, 0), (8, 0)),
+        ];
+        document.type_multi(&mut cursors, 'x').unwrap();
+        assert_eq!(rows(&document), ["x bar x", "baz x"]);
+        assert_eq!(cursors, [caret(1, 0), caret(7, 0), caret(5, 1)]);
+        document.undo();
+        assert_eq!(rows(&document), ["foo bar foo", "baz foo"]);
+    }
+
+    #[test]
+    fn type_multi_shifts_cursors_after_each_edit() {
+        let mut document = Document::from_str("abc\ndef\nghi\n");
+        let mut cursors = vec![
+            caret(1, 0),
+            caret(2, 0),
+            selection((2, 1), (1, 2)),
+            caret(3, 2),
+        ];
+        document.type_multi(&mut cursors, '-').unwrap();
+        assert_eq!(rows(&document), ["a-b-c", "de-hi-"]);
+        assert_eq!(
+            cursors,
+            [caret(2, 0), caret(4, 0), caret(3, 1), caret(6, 1)]
+        );
+        let mut document = Document::from_str("ab\ncd\n");
+        let mut cursors = vec![caret(1, 0), caret(1, 1)];
+        document.type_multi(&mut cursors, '\n').unwrap();
+        assert_eq!(rows(&document), ["a", "b", "c", "d"]);
+        assert_eq!(cursors, [caret(0, 1), caret(0, 3)]);
+    }
+
+    #[test]
+    fn type_multi_merges_overlapping_selections() {
+        let mut document = Document::from_str("abcdef\n");
+        let mut cursors = vec![
+            selection((0, 0), (3, 0)),
+            selection((2, 0), (5, 0)),
+            caret(1, 0),
+        ];
+        document.type_multi(&mut cursors, 'z').unwrap();
+        assert_eq!(rows(&document), ["zf"]);
+        assert_eq!(cursors, [caret(1, 0)]);
+        assert!(document.type_multi(&mut vec![caret(0, 9)], 'q').is_err());
+    }
+
+    #[test]
+    fn rows_with_only_a_partial_grapheme_match_are_dimmed() {
+        let mut document = Document::from_str("ab\u{301}\nab\n");
+        document.set_dim_unmatched(true);
+        document.highlight(Some("ab"), SearchOptions::default());
+        assert_eq!(document.row(0).unwrap().highlighting(), [Type::Dimmed; 2]);
+        assert_eq!(document.row(1).unwrap().highlighting(), [Type::Search; 2]);
+    }
+
+    #[test]
+    fn update_swap_captures_edits_after_the_first() {
+        let path = temp_file("swap_update.txt", "one\n");
+        let mut document = Document::open(&path).unwrap();
+        assert!(!document.update_swap().unwrap());
+        document.insert(&Position::default(), 'x').unwrap();
+        assert!(!document.update_swap().unwrap());
+        document.insert(&Position::default(), 'y').unwrap();
+        let recovered = Document::recover_from_swap(&path).unwrap();
+        assert_eq!(rows(&recovered), ["xone"]);
+        assert!(document.update_swap().unwrap());
+        assert!(!document.update_swap().unwrap());
+        let recovered = Document::recover_from_swap(&path).unwrap();
+        assert_eq!(rows(&recovered), ["yxone"]);
+        document.save().unwrap();
+        assert!(!document.update_swap().unwrap());
+        assert!(!Document::has_swap(&path));
+    }
+
+    #[test]
+    fn recovering_from_swap_keeps_the_line_ending() {
+        let path = temp_file("swap_crlf.txt", "one\r\ntwo\r\n");
+        let mut document = Document::open(&path).unwrap();
+        document.insert(&Position::default(), 'x').unwrap();
+        let mut recovered = Document::recover_from_swap(&path).unwrap();
+        assert_eq!(recovered.line_ending(), LineEnding::CrLf);
+        recovered.save().unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "xone\r\ntwo\r\n");
+    }
+
+    #[test]
+    fn marking_the_buffer_clean_removes_the_swap_file() {
+        let path = temp_file("swap_clean.txt", "one\n");
+        let mut document = Document::open(&path).unwrap();
+        document.insert(&Position::default(), 'x').unwrap();
+        assert!(Document::has_swap(&path));
+        document.set_dirty(false);
+        assert!(!Document::has_swap(&path));
+    }
+
+    #[test]
+    fn saving_without_a_filename_is_an_error() {
+        let mut document = Document::from_str("a\n");
+        let error = document.save().unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        assert!(document.force_save().is_err());
+    }
+
+    #[test]
+    fn a_failed_rename_removes_the_temporary_file() {
+        let file = temp_file("rename_blocker", "");
+        let path = Path::new(&file).with_extension("dir");
+        fs::create_dir_all(&path).unwrap();
+        fs::write(path.join("keep"), "").unwrap();
+        let mut document = Document::from_str("a\n");
+        document.set_filename(Some(path.to_string_lossy().into_owned()));
+        assert!(document.save().is_err());
+        assert!(!temp_path(&path).exists());
+    }
+
+    #[test]
+    fn todo_text_stops_where_the_comment_ends() {
+        let mut document = Document::from_str("/* TODO x */ code()\n// FIXME see http://a.b\n");
+        document.set_syntax(Syntax::from_filename("a.rs"));
+        document.highlight(Some("see"), SearchOptions::default());
+        // Stand in for block comment highlighting, which no syntax has yet.
+        let row = &mut document.rows[0];
+        for x in 0..12 {
+            row.set_highlight(x, Type::Comment);
+        }
+        for x in 3..7 {
+            row.set_highlight(x, Type::Todo);
+        }
+        let todos = document.todos();
+        assert_eq!(todos[0], (Position { x: 3, y: 0 }, "TODO x */".to_string()));
+        assert_eq!(todos[1].1, "FIXME see http://a.b");
+    }
+}
@@ -0,0 +1,50 @@
+use termion::color;
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum Type {
+    None,
+    Number,
+    Meta,
+    Search,
+    SearchFuzzy,
+    Comment,
+    Todo,
+    MatchBracket,
+    ActiveLine,
+    Control,
+    Url,
+    IndentGuide,
+    Continuation,
+    String,
+    Heading,
+    Emphasis,
+    Strong,
+    Code,
+    Dimmed,
+}
+
+impl Type {
+    pub fn to_color(self) -> impl color::Color {
+        match self {
+            Type::Number => color::Rgb(220, 163, 163),
+            Type::Meta => color::Rgb(190, 140, 220),
+            Type::Search => color::Rgb(38, 139, 210),
+            Type::SearchFuzzy => color::Rgb(120, 180, 220),
+            Type::Comment => color::Rgb(133, 153, 0),
+            Type::Todo => color::Rgb(255, 200, 0),
+            Type::MatchBracket => color::Rgb(255, 110, 200),
+            Type::ActiveLine => color::Rgb(40, 40, 40),
+            Type::Control => color::Rgb(255, 85, 85),
+            Type::Url => color::Rgb(90, 160, 255),
+            Type::IndentGuide => color::Rgb(55, 55, 55),
+            Type::Continuation => color::Rgb(255, 140, 60),
+            Type::String => color::Rgb(211, 54, 130),
+            Type::Heading => color::Rgb(255, 175, 95),
+            Type::Emphasis => color::Rgb(175, 215, 135),
+            Type::Strong => color::Rgb(255, 215, 135),
+            Type::Code => color::Rgb(135, 215, 215),
+            Type::Dimmed => color::Rgb(100, 100, 100),
+            Type::None => color::Rgb(255, 255, 255),
+        }
+    }
+}
@@ -0,0 +1,113 @@
+mod document;
+pub mod highlighting;
+mod row;
+mod search;
+mod syntax;
+
+pub use document::{Document, EditError, LineEnding, OpenOptions, PasteOptions};
+pub use row::{IndentKind, RenderOptions, Row, Span};
+pub use search::{SearchResult, SearchState};
+pub use syntax::{MetaStyle, Syntax, SyntaxContext};
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Position {
+    pub x: usize,
+    pub y: usize,
+}
+
+/// A search hit: where it starts and how many graphemes it spans.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Match {
+    pub start: Position,
+    pub len: usize,
+}
+
+/// A range of text between where the selection was started and where the
+/// cursor is now. The two are equal for a plain cursor.
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Selection {
+    pub anchor: Position,
+    pub cursor: Position,
+}
+
+impl Selection {
+    pub fn is_empty(&self) -> bool {
+        self.anchor == self.cursor
+    }
+
+    /// Returns the two ends in document order.
+    pub fn range(&self) -> (Position, Position) {
+        if (self.anchor.y, self.anchor.x) <= (self.cursor.y, self.cursor.x) {
+            (self.anchor, self.cursor)
+        } else {
+            (self.cursor, self.anchor)
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SearchDirection {
+    Forward,
+    Backward,
+}
+
+/// What a column counts: grapheme clusters, as the editor shows them, or
+/// `char`s, as integrations reporting codepoint offsets expect.
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CursorModel {
+    #[default]
+    Grapheme,
+    Codepoint,
+}
+
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SearchOptions {
+    pub ignore_case: bool,
+    /// Skip matches that start inside the previous one, so "aa" is found
+    /// once in "aaa" rather than twice. Off by default.
+    pub skip_overlapping: bool,
+}
+
+/// Shortens `s` to fit in `max_width` display columns, cutting only between
+/// graphemes. When anything is cut off the result ends in `…`, which counts
+/// toward the width.
+pub fn truncate(s: &str, max_width: usize) -> String {
+    if s.width() <= max_width {
+        return s.to_string();
+    }
+    if max_width == 0 {
+        return String::new();
+    }
+    let mut result = String::new();
+    let mut width = 1;
+    for grapheme in s.graphemes(true) {
+        width += grapheme.width();
+        if width > max_width {
+            break;
+        }
+        result.push_str(grapheme);
+    }
+    result.push('…');
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn truncate_cuts_wide_characters_at_grapheme_boundaries() {
+        assert_eq!(truncate("日本語テキスト", 7), "日本語…");
+        assert_eq!(truncate("日本語", 6), "日本語");
+        assert_eq!(truncate("abcdef", 1), "…");
+        assert_eq!(truncate("abc", 0), "");
+    }
+
+    #[test]
+    fn truncate_keeps_combining_marks_with_their_base() {
+        let text = "e\u{301}e\u{301}e\u{301}e\u{301}";
+        assert_eq!(truncate(text, 3), "e\u{301}e\u{301}…");
+    }
+}
@@ -0,0 +1,1845 @@
+use crate::highlighting;
+use crate::{CursorModel, MetaStyle, SearchDirection, SearchOptions, Syntax};
+use std::cmp;
+use termion::color;
+use unicode_segmentation::{GraphemeCursor, UnicodeSegmentation};
+use unicode_width::UnicodeWidthStr;
+
+/// Settings for `Row::render_with`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RenderOptions {
+    pub tab_width: usize,
+    /// Spaces written before the content, e.g. to line up with a gutter.
+    /// The padding is never highlighted.
+    pub left_pad: usize,
+    /// Background for tabs in the leading indent, if any.
+    pub leading_tab: Option<highlighting::Type>,
+    /// Background for tabs after the first non-whitespace grapheme, which
+    /// are usually there for alignment.
+    pub interior_tab: Option<highlighting::Type>,
+    /// Tints every leading space that starts a tab stop with
+    /// `Type::IndentGuide`, drawing faint vertical guides through nested
+    /// code.
+    pub indent_guides: bool,
+}
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        Self {
+            tab_width: 4,
+            left_pad: 0,
+            leading_tab: None,
+            interior_tab: None,
+            indent_guides: false,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IndentKind {
+    None,
+    Tabs,
+    Spaces,
+    Mixed,
+}
+
+/// A run of rendered text sharing one highlighting type.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Span {
+    pub text: String,
+    pub highlighting: highlighting::Type,
+    /// Background tint, e.g. `Type::ActiveLine` for the cursor's row.
+    pub background: Option<highlighting::Type>,
+}
+
+pub struct Row {
+    string: String,
+    highlighting: Vec<highlighting::Type>,
+    len: usize,
+    /// Set whenever the content or highlighting changes, until the editor
+    /// calls `mark_drawn`. Unrelated to the document's save state.
+    needs_redraw: bool,
+}
+
+impl Default for Row {
+    fn default() -> Self {
+        Self::from("")
+    }
+}
+
+impl From<&str> for Row {
+    fn from(slice: &str) -> Self {
+        Self {
+            string: String::from(slice),
+            highlighting: Vec::new(),
+            len: slice.graphemes(true).count(),
+            needs_redraw: true,
+        }
+    }
+}
+
+impl Row {
+    pub fn render(&self, start: usize, end: usize) -> String {
+        self.render_with(start, end, &RenderOptions::default())
+    }
+
+    pub fn render_with(&self, start: usize, end: usize, options: &RenderOptions) -> String {
+        let mut result = " ".repeat(options.left_pad);
+        let mut current_highlighting = highlighting::Type::None;
+        let mut current_background = None;
+        for span in self.spans(start, end, options) {
+            if span.highlighting != current_highlighting {
+                current_highlighting = span.highlighting;
+                let start_highlight = format!("{}", color::Fg(span.highlighting.to_color()));
+                result.push_str(&start_highlight);
+            }
+            if span.background != current_background {
+                current_background = span.background;
+                let background = match span.background {
+                    Some(background) => format!("{}", color::Bg(background.to_color())),
+                    None => format!("{}", color::Bg(color::Reset)),
+                };
+                result.push_str(&background);
+            }
+            result.push_str(&span.text);
+        }
+        if current_background.is_some() {
+            result.push_str(&format!("{}", color::Bg(color::Reset)));
+        }
+        let end_highlight = format!("{}", color::Fg(color::Reset));
+        result.push_str(&end_highlight);
+        result
+    }
+
+    /// Like `render`, but also returns which grapheme each display column of
+    /// the output belongs to, e.g. to turn a mouse click into a position.
+    /// A tab or wide grapheme owns every column it covers.
+    pub fn render_with_map(
+        &self,
+        start: usize,
+        end: usize,
+        tab_width: usize,
+    ) -> (String, Vec<usize>) {
+        let options = RenderOptions {
+            tab_width,
+            ..RenderOptions::default()
+        };
+        let mut map = Vec::new();
+        let mut col = 0;
+        for (index, grapheme) in self.string[..].graphemes(true).enumerate().take(end) {
+            let width = grapheme_width(grapheme, col, tab_width);
+            col += width;
+            if index >= start {
+                map.extend(std::iter::repeat_n(index, width));
+            }
+        }
+        (self.render_with(start, end, &options), map)
+    }
+
+    /// Compares the rendering of `[start, end)` against `prev`, an earlier
+    /// rendering of the same screen line, and returns only what changed as
+    /// `(column, text)` patches. Each patch carries the escapes for its own
+    /// colors, and columns the new rendering no longer reaches are cleared
+    /// with spaces.
+    pub fn render_diff(
+        &self,
+        prev: &str,
+        start: usize,
+        end: usize,
+        tab_width: usize,
+    ) -> Vec<(usize, String)> {
+        let options = RenderOptions {
+            tab_width,
+            ..RenderOptions::default()
+        };
+        let current = cells(&self.render_with(start, end, &options));
+        let previous = cells(prev);
+        let blank = Cell::default();
+        let mut patches = Vec::new();
+        let mut col = 0;
+        while col < cmp::max(current.len(), previous.len()) {
+            let cell = current.get(col).unwrap_or(&blank);
+            if previous.get(col) == Some(cell) {
+                col += 1;
+                continue;
+            }
+            // Start on the first column of a wide grapheme, never halfway.
+            while col > 0 && current.get(col).is_some_and(|cell| cell.text.is_empty()) {
+                col -= 1;
+            }
+            let first = col;
+            let mut text = String::new();
+            let mut style = None;
+            while col < cmp::max(current.len(), previous.len()) {
+                let cell = current.get(col).unwrap_or(&blank);
+                if previous.get(col) == Some(cell) && !cell.text.is_empty() {
+                    break;
+                }
+                if style != Some((&cell.foreground, &cell.background)) {
+                    style = Some((&cell.foreground, &cell.background));
+                    text.push_str(&cell.foreground);
+                    text.push_str(&cell.background);
+                }
+                text.push_str(if col < current.len() { &cell.text } else { " " });
+                col += 1;
+            }
+            text.push_str(&format!(
+                "{}{}",
+                color::Fg(color::Reset),
+                color::Bg(color::Reset)
+            ));
+            patches.push((first, text));
+        }
+        patches
+    }
+
+    /// Splits the graphemes `[start, end)` into runs of equal highlighting,
+    /// with tabs already expanded, for callers that draw the colors
+    /// themselves.
+    pub fn render_spans(&self, start: usize, end: usize) -> Vec<Span> {
+        self.spans(start, end, &RenderOptions::default())
+    }
+
+    fn spans(&self, start: usize, end: usize, options: &RenderOptions) -> Vec<Span> {
+        let end = cmp::min(end, self.len);
+        let start = cmp::min(start, end);
+        let mut spans: Vec<Span> = Vec::new();
+        let mut col = 0;
+        let (indent, _) = self.leading_indent();
+        for (index, grapheme) in self.string[..].graphemes(true).enumerate().take(end) {
+            let width = grapheme_width(grapheme, col, options.tab_width);
+            let guide = options.indent_guides
+                && index < indent
+                && col % cmp::max(options.tab_width, 1) == 0;
+            col += width;
+            if index < start {
+                continue;
+            }
+            let highlighting_type = self.display_type(index, grapheme);
+            let background = match grapheme {
+                "\t" if index < indent => options.leading_tab,
+                "\t" => options.interior_tab,
+                " " if guide => Some(highlighting::Type::IndentGuide),
+                _ => None,
+            };
+            let span = match spans.last_mut() {
+                Some(span)
+                    if span.highlighting == highlighting_type && span.background == background =>
+                {
+                    span
+                }
+                _ => {
+                    spans.push(Span {
+                        text: String::new(),
+                        highlighting: highlighting_type,
+                        background,
+                    });
+                    spans.last_mut().expect("a span was just pushed")
+                }
+            };
+            if grapheme == "\t" {
+                span.text.push_str(&" ".repeat(width));
+            } else if let Some(caret) = caret_notation(grapheme) {
+                span.text.push_str(&caret);
+            } else {
+                span.text.push_str(grapheme);
+            }
+        }
+        spans
+    }
+
+    /// Renders the display columns `[col_start, col_end)`. A wide grapheme
+    /// or tab cut by either edge is drawn as spaces for its visible part, so
+    /// scrolling never shows half a glyph.
+    pub fn render_cols(&self, col_start: usize, col_end: usize, tab_width: usize) -> String {
+        let mut result = String::new();
+        let mut current_highlighting = highlighting::Type::None;
+        let first = self.rx_to_cx(col_start, tab_width);
+        let mut col = self.cx_to_rx(first, tab_width);
+        for (index, grapheme) in self.string[..].graphemes(true).enumerate().skip(first) {
+            if col >= col_end {
+                break;
+            }
+            let next = col + grapheme_width(grapheme, col, tab_width);
+            let visible_start = cmp::max(col, col_start);
+            let visible_end = cmp::min(next, col_end);
+            push_highlight(
+                &mut result,
+                &mut current_highlighting,
+                self.display_type(index, grapheme),
+            );
+            if grapheme == "\t" || visible_start > col || visible_end < next {
+                result.push_str(&" ".repeat(visible_end.saturating_sub(visible_start)));
+            } else if let Some(caret) = caret_notation(grapheme) {
+                result.push_str(&caret);
+            } else {
+                result.push_str(grapheme);
+            }
+            col = next;
+        }
+        let end_highlight = format!("{}", color::Fg(color::Reset));
+        result.push_str(&end_highlight);
+        result
+    }
+
+    /// Returns the row's width in display columns.
+    pub fn width(&self, tab_width: usize) -> usize {
+        self.cx_to_rx(self.len, tab_width)
+    }
+
+    /// Converts a grapheme index into the display column it starts at.
+    pub fn cx_to_rx(&self, cx: usize, tab_width: usize) -> usize {
+        self.string[..]
+            .graphemes(true)
+            .take(cx)
+            .fold(0, |rx, grapheme| {
+                rx + grapheme_width(grapheme, rx, tab_width)
+            })
+    }
+
+    /// Returns how many display columns the grapheme at `cx` covers, e.g. so
+    /// the cursor can span a whole tab. Past the end of the row this is 1,
+    /// the width of the cursor on the empty cell there.
+    pub fn grapheme_width_at(&self, cx: usize, tab_width: usize) -> usize {
+        self.graphemes().nth(cx).map_or(1, |grapheme| {
+            grapheme_width(grapheme, self.cx_to_rx(cx, tab_width), tab_width)
+        })
+    }
+
+    /// Converts a display column into the index of the grapheme covering it,
+    /// or `len()` if the column lies past the end of the row.
+    pub fn rx_to_cx(&self, rx: usize, tab_width: usize) -> usize {
+        let mut current_rx = 0;
+        for (cx, grapheme) in self.string[..].graphemes(true).enumerate() {
+            current_rx += grapheme_width(grapheme, current_rx, tab_width);
+            if current_rx > rx {
+                return cx;
+            }
+        }
+        self.len
+    }
+
+    /// Soft-wraps the row at `width` display columns and returns the
+    /// grapheme range `[start, end)` of each screen line, ready for
+    /// `render`. An empty row is one empty line. A grapheme wider than
+    /// `width` gets a line to itself.
+    pub fn wrap(&self, width: usize, tab_width: usize) -> Vec<(usize, usize)> {
+        let width = cmp::max(width, 1);
+        let mut lines = Vec::new();
+        let mut start = 0;
+        let mut line_width = 0;
+        let mut col = 0;
+        for (index, grapheme) in self.string[..].graphemes(true).enumerate() {
+            let grapheme_width = grapheme_width(grapheme, col, tab_width);
+            if line_width + grapheme_width > width && index > start {
+                lines.push((start, index));
+                start = index;
+                line_width = 0;
+            }
+            line_width += grapheme_width;
+            col += grapheme_width;
+        }
+        lines.push((start, self.len));
+        lines
+    }
+
+    /// Returns the byte offset at which the grapheme `index` starts, or the
+    /// string's length if `index` is past the end.
+    fn byte_offset(&self, index: usize) -> usize {
+        self.string[..]
+            .grapheme_indices(true)
+            .nth(index)
+            .map_or(self.string.len(), |(byte, _)| byte)
+    }
+
+    /// Returns whether the byte offset `byte`, a char boundary, is also a
+    /// grapheme boundary.
+    fn is_grapheme_boundary(&self, byte: usize) -> bool {
+        GraphemeCursor::new(byte, self.string.len(), true)
+            .is_boundary(&self.string, 0)
+            .unwrap_or(false)
+    }
+
+    /// Converts a grapheme index into the byte offset it starts at, e.g. for
+    /// tools that report byte columns. Past the end this is the row's byte
+    /// length.
+    pub fn grapheme_to_byte(&self, index: usize) -> usize {
+        self.byte_offset(index)
+    }
+
+    /// Converts a byte offset into the index of the grapheme containing it,
+    /// or `len()` past the end of the row.
+    pub fn byte_to_grapheme(&self, byte: usize) -> usize {
+        self.string[..]
+            .grapheme_indices(true)
+            .position(|(start, grapheme)| byte < start + grapheme.len())
+            .unwrap_or(self.len)
+    }
+
+    /// Returns the type a grapheme is drawn with: its highlighting, except
+    /// for control characters, which always stand out as `Type::Control`.
+    fn display_type(&self, index: usize, grapheme: &str) -> highlighting::Type {
+        if caret_notation(grapheme).is_some() {
+            return highlighting::Type::Control;
+        }
+        self.highlighting
+            .get(index)
+            .copied()
+            .unwrap_or(highlighting::Type::None)
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn insert(&mut self, at: usize, c: char) {
+        if at >= self.len() {
+            self.string.push(c);
+        } else {
+            let mut result = String::new();
+            for (index, grapheme) in self.string[..].graphemes(true).enumerate() {
+                if index == at {
+                    result.push(c);
+                }
+                result.push_str(grapheme);
+            }
+            self.string = result;
+        }
+        self.len = self.string[..].graphemes(true).count();
+        self.needs_redraw = true;
+    }
+
+    /// Returns the row's length in `model` units.
+    pub fn len_in(&self, model: CursorModel) -> usize {
+        match model {
+            CursorModel::Grapheme => self.len,
+            CursorModel::Codepoint => self.string.chars().count(),
+        }
+    }
+
+    /// Converts a grapheme index into a column in `model` units.
+    pub fn to_model_index(&self, index: usize, model: CursorModel) -> usize {
+        match model {
+            CursorModel::Grapheme => index,
+            CursorModel::Codepoint => self.string[..self.byte_offset(index)].chars().count(),
+        }
+    }
+
+    /// Converts a column in `model` units into the index of the grapheme
+    /// containing it.
+    pub fn from_model_index(&self, index: usize, model: CursorModel) -> usize {
+        let offset = self.offset_in(index, model);
+        if offset >= self.string.len() {
+            return self.len;
+        }
+        self.string[..]
+            .grapheme_indices(true)
+            .take_while(|&(byte, _)| byte <= offset)
+            .count()
+            - 1
+    }
+
+    /// Like `insert`, but `at` is counted in `model` units, so a codepoint
+    /// can also land inside a grapheme cluster.
+    pub fn insert_in(&mut self, at: usize, c: char, model: CursorModel) {
+        if model == CursorModel::Grapheme {
+            self.insert(at, c);
+            return;
+        }
+        let offset = self.offset_in(at, model);
+        self.string.insert(offset, c);
+        self.len = self.string[..].graphemes(true).count();
+        self.needs_redraw = true;
+    }
+
+    /// Like `delete`, but `at` is counted in `model` units.
+    pub fn delete_in(&mut self, at: usize, model: CursorModel) {
+        if model == CursorModel::Grapheme {
+            self.delete(at);
+            return;
+        }
+        if at >= self.len_in(model) {
+            return;
+        }
+        let start = self.offset_in(at, model);
+        let end = self.offset_in(at + 1, model);
+        self.string.replace_range(start..end, "");
+        self.len = self.string[..].graphemes(true).count();
+        self.needs_redraw = true;
+    }
+
+    /// Returns the byte offset of column `index` in `model` units, or the
+    /// string's length past the end.
+    fn offset_in(&self, index: usize, model: CursorModel) -> usize {
+        match model {
+            CursorModel::Grapheme => self.byte_offset(index),
+            CursorModel::Codepoint => self
+                .string
+                .char_indices()
+                .nth(index)
+                .map_or(self.string.len(), |(byte, _)| byte),
+        }
+    }
+
+    /// Like `insert`, but first pads a row shorter than `at` with spaces so
+    /// `c` lands exactly at grapheme `at`, e.g. for a virtual-column cursor.
+    pub fn insert_padded(&mut self, at: usize, c: char) {
+        if at > self.len {
+            self.string.push_str(&" ".repeat(at - self.len));
+            self.len = at;
+            self.needs_redraw = true;
+        }
+        self.insert(at, c);
+    }
+
+    /// Replaces the graphemes in `[start, end)` with `with` in one pass.
+    /// Both ends are clamped to the row, like `split`. Re-highlighting is up
+    /// to the document.
+    pub fn replace_range(&mut self, start: usize, end: usize, with: &str) {
+        let end = cmp::min(end, self.len);
+        let start = cmp::min(start, end);
+        let range = self.byte_offset(start)..self.byte_offset(end);
+        self.string.replace_range(range, with);
+        self.len = self.string[..].graphemes(true).count();
+        self.needs_redraw = true;
+    }
+
+    /// Appends spaces until the row is `width` display columns wide, e.g. to
+    /// line up a column block. Wider rows are left alone. As with the other
+    /// edits, re-highlighting is up to the document.
+    pub fn pad_to(&mut self, width: usize, tab_width: usize) {
+        let current = self.width(tab_width);
+        if current < width {
+            self.string.push_str(&" ".repeat(width - current));
+            self.len += width - current;
+            self.needs_redraw = true;
+        }
+    }
+
+    pub fn delete(&mut self, at: usize) {
+        if at >= self.len() {
+            return;
+        }
+        let mut result = String::new();
+        for (index, grapheme) in self.string[..].graphemes(true).enumerate() {
+            if index != at {
+                result.push_str(grapheme);
+            }
+        }
+        self.string = result;
+        self.len -= 1;
+        self.needs_redraw = true;
+    }
+
+    pub fn append(&mut self, new: &Self) {
+        self.string.push_str(&new.string);
+        self.len = self.string[..].graphemes(true).count();
+        self.needs_redraw = true;
+    }
+
+    /// Keeps the first `at` graphemes and returns the rest as a new row.
+    /// `at` is clamped to the row's length, so splitting past the end leaves
+    /// the row intact and returns an empty one. Either way the two halves
+    /// concatenate back to the original.
+    pub fn split(&mut self, at: usize) -> Self {
+        let at = cmp::min(at, self.len);
+        let remainder = self.string.split_off(self.byte_offset(at));
+        self.len = at;
+        self.highlighting.clear();
+        self.needs_redraw = true;
+        Self::from(&remainder[..])
+    }
+
+    pub fn find(&self, query: &str, at: usize, direction: SearchDirection) -> Option<usize> {
+        if at > self.len || query.is_empty() {
+            return None;
+        }
+        // Search the borrowed halves of the string directly and walk the
+        // grapheme boundaries alongside the matches, so no substring has to
+        // be collected. Matches starting or ending inside a grapheme are
+        // skipped, as in the grapheme-wise ignore-case search.
+        let at_byte = self.byte_offset(at);
+        if direction == SearchDirection::Forward {
+            let mut graphemes = self.string[at_byte..]
+                .grapheme_indices(true)
+                .zip(at..)
+                .peekable();
+            for (byte, _) in self.string[at_byte..].match_indices(query) {
+                while let Some(&((grapheme_byte, _), index)) = graphemes.peek() {
+                    match grapheme_byte.cmp(&byte) {
+                        cmp::Ordering::Less => {
+                            graphemes.next();
+                        }
+                        cmp::Ordering::Equal
+                            if self.is_grapheme_boundary(at_byte + byte + query.len()) =>
+                        {
+                            return Some(index)
+                        }
+                        cmp::Ordering::Equal | cmp::Ordering::Greater => break,
+                    }
+                }
+            }
+        } else {
+            let mut graphemes = self.string[..at_byte]
+                .grapheme_indices(true)
+                .rev()
+                .zip((0..at).rev())
+                .peekable();
+            for (byte, _) in self.string[..at_byte].rmatch_indices(query) {
+                while let Some(&((grapheme_byte, _), index)) = graphemes.peek() {
+                    match grapheme_byte.cmp(&byte) {
+                        cmp::Ordering::Greater => {
+                            graphemes.next();
+                        }
+                        cmp::Ordering::Equal if self.is_grapheme_boundary(byte + query.len()) => {
+                            return Some(index)
+                        }
+                        cmp::Ordering::Equal | cmp::Ordering::Less => break,
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    /// Like `find`, but honours `options`. Returns the grapheme index of the
+    /// match and its length in graphemes.
+    pub fn find_match(
+        &self,
+        query: &str,
+        at: usize,
+        direction: SearchDirection,
+        options: SearchOptions,
+    ) -> Option<(usize, usize)> {
+        let needle: Vec<&str> = query.graphemes(true).collect();
+        if !options.ignore_case {
+            return self
+                .find(query, at, direction)
+                .map(|index| (index, needle.len()));
+        }
+        if at > self.len || needle.is_empty() {
+            return None;
+        }
+        let graphemes: Vec<&str> = self.string[..].graphemes(true).collect();
+        let found = if direction == SearchDirection::Forward {
+            (at..=self.len.saturating_sub(needle.len()))
+                .find(|&index| graphemes_match(&graphemes[index..], &needle, options))
+        } else {
+            (0..=at.checked_sub(needle.len())?)
+                .rev()
+                .find(|&index| graphemes_match(&graphemes[index..], &needle, options))
+        };
+        found.map(|index| (index, needle.len()))
+    }
+
+    /// Returns the grapheme index of every match of `query` in the row, in
+    /// order, as found by `find_match`. Whether matches may overlap is up to
+    /// `options.skip_overlapping`.
+    pub fn find_all(&self, query: &str, options: SearchOptions) -> Vec<usize> {
+        let mut found = Vec::new();
+        let mut at = 0;
+        while let Some((index, len)) = self.find_match(query, at, SearchDirection::Forward, options)
+        {
+            found.push(index);
+            at = index
+                + if options.skip_overlapping {
+                    cmp::max(len, 1)
+                } else {
+                    1
+                };
+        }
+        found
+    }
+
+    /// Returns the index of the nearest grapheme equal to `c` after `from`,
+    /// or before it when `forward` is false, like Vim's `f` and `F`.
+    pub fn find_char(&self, from: usize, c: char, forward: bool) -> Option<usize> {
+        let mut buffer = [0; 4];
+        let target: &str = c.encode_utf8(&mut buffer);
+        let mut graphemes = self.grapheme_indices();
+        if forward {
+            graphemes.find(|&(index, grapheme)| index > from && grapheme == target)
+        } else {
+            graphemes
+                .take(from)
+                .filter(|&(_, grapheme)| grapheme == target)
+                .last()
+        }
+        .map(|(index, _)| index)
+    }
+
+    /// Returns whether `find_match` finds `query` starting exactly at
+    /// grapheme `at`.
+    pub(crate) fn matches_at(&self, query: &str, at: usize, options: SearchOptions) -> bool {
+        self.find_match(query, at, SearchDirection::Forward, options)
+            .is_some_and(|(index, _)| index == at)
+    }
+
+    /// Splits the row after every `delim`, keeping the delimiter at the end of
+    /// its piece, e.g. to show a minified line as pseudo-lines.
+    pub fn split_on(&self, delim: char) -> Vec<String> {
+        self.string
+            .split_inclusive(delim)
+            .map(String::from)
+            .collect()
+    }
+
+    /// Returns the number of leading whitespace graphemes and whether they
+    /// are tabs, spaces or a mix of both.
+    pub fn leading_indent(&self) -> (usize, IndentKind) {
+        let mut count = 0;
+        let mut kind = IndentKind::None;
+        for c in self.string.chars() {
+            let current = match c {
+                '\t' => IndentKind::Tabs,
+                ' ' => IndentKind::Spaces,
+                _ => break,
+            };
+            kind = match kind {
+                IndentKind::None => current,
+                kind if kind == current => kind,
+                _ => IndentKind::Mixed,
+            };
+            count += 1;
+        }
+        (count, kind)
+    }
+
+    /// Iterates over the row's grapheme clusters without allocating.
+    pub fn graphemes(&self) -> impl Iterator<Item = &str> {
+        self.string.graphemes(true)
+    }
+
+    /// Returns the whole grapheme cluster at `index`, or `None` past the end
+    /// of the row.
+    pub fn grapheme_at(&self, index: usize) -> Option<&str> {
+        self.graphemes().nth(index)
+    }
+
+    /// Like `graphemes`, but paired with each grapheme's index in the row,
+    /// the same index `insert`, `delete` and `Position::x` use.
+    pub fn grapheme_indices(&self) -> impl Iterator<Item = (usize, &str)> {
+        self.graphemes().enumerate()
+    }
+
+    /// Returns the range `[start, end)` of the word containing grapheme `at`,
+    /// or `None` if it is not a word character. Alphanumerics and anything
+    /// in `word_chars` make up words.
+    pub fn word_at(&self, at: usize, word_chars: &str) -> Option<(usize, usize)> {
+        let graphemes: Vec<&str> = self.graphemes().collect();
+        if !is_word_char(graphemes.get(at)?, word_chars) {
+            return None;
+        }
+        let start = graphemes[..at]
+            .iter()
+            .rposition(|grapheme| !is_word_char(grapheme, word_chars))
+            .map_or(0, |index| index + 1);
+        let end = graphemes[at..]
+            .iter()
+            .position(|grapheme| !is_word_char(grapheme, word_chars))
+            .map_or(graphemes.len(), |index| at + index);
+        Some((start, end))
+    }
+
+    /// Returns the start of the first word after grapheme `at`, or `len()`
+    /// if no word follows on this row.
+    pub fn next_word_boundary(&self, at: usize, word_chars: &str) -> usize {
+        let mut in_word = true;
+        for (index, grapheme) in self.grapheme_indices().skip(at) {
+            let is_word = is_word_char(grapheme, word_chars);
+            if is_word && !in_word {
+                return index;
+            }
+            in_word = is_word;
+        }
+        self.len
+    }
+
+    /// Returns whether the graphemes `[start, end)` form whole words, i.e.
+    /// no word character touches them on either side.
+    pub(crate) fn is_whole_word(&self, start: usize, end: usize, word_chars: &str) -> bool {
+        let before = start
+            .checked_sub(1)
+            .and_then(|index| self.grapheme_at(index));
+        let after = self.grapheme_at(end);
+        [before, after]
+            .into_iter()
+            .flatten()
+            .all(|grapheme| !is_word_char(grapheme, word_chars))
+    }
+
+    /// Returns each word's starting grapheme index, length in graphemes and
+    /// text, e.g. for a caller-supplied spellchecker.
+    pub fn word_spans(&self) -> Vec<(usize, usize, String)> {
+        let mut spans = Vec::new();
+        let mut index = 0;
+        let mut offset = 0;
+        for (start, word) in self.string.unicode_word_indices() {
+            index += self.string[offset..start].graphemes(true).count();
+            let len = word.graphemes(true).count();
+            spans.push((index, len, word.to_string()));
+            index += len;
+            offset = start + word.len();
+        }
+        spans
+    }
+
+    /// Returns whether the row starts with `prefix` made of whole graphemes,
+    /// so `"e"` is not a prefix of `"e\u{301}"`.
+    pub fn starts_with(&self, prefix: &str) -> bool {
+        self.string.starts_with(prefix) && self.is_grapheme_boundary(prefix.len())
+    }
+
+    /// Returns whether the row ends with `suffix` made of whole graphemes.
+    pub fn ends_with(&self, suffix: &str) -> bool {
+        self.string.ends_with(suffix) && self.is_grapheme_boundary(self.string.len() - suffix.len())
+    }
+
+    /// Returns the row's content with leading whitespace graphemes skipped.
+    /// A combining mark on a space keeps that grapheme.
+    pub fn trimmed_start(&self) -> &str {
+        let start = self
+            .string
+            .grapheme_indices(true)
+            .find(|(_, grapheme)| !grapheme.chars().all(char::is_whitespace))
+            .map_or(self.string.len(), |(byte, _)| byte);
+        &self.string[start..]
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        self.string.as_bytes()
+    }
+
+    pub(crate) fn as_str(&self) -> &str {
+        &self.string
+    }
+
+    pub fn highlighting(&self) -> &[highlighting::Type] {
+        &self.highlighting
+    }
+
+    /// Returns the highlighting of the grapheme at `index`, or `None` past
+    /// the end of the row.
+    pub(crate) fn highlight_at(&self, index: usize) -> Option<highlighting::Type> {
+        if index >= self.len {
+            return None;
+        }
+        Some(
+            self.highlighting
+                .get(index)
+                .copied()
+                .unwrap_or(highlighting::Type::None),
+        )
+    }
+
+    pub(crate) fn set_highlight(&mut self, index: usize, highlighting_type: highlighting::Type) {
+        if index >= self.len {
+            return;
+        }
+        if self.highlighting.len() < self.len {
+            self.highlighting.resize(self.len, highlighting::Type::None);
+        }
+        if self.highlighting[index] != highlighting_type {
+            self.highlighting[index] = highlighting_type;
+            self.needs_redraw = true;
+        }
+    }
+
+    pub fn needs_redraw(&self) -> bool {
+        self.needs_redraw
+    }
+
+    /// Clears the redraw flag once the editor has drawn the row.
+    pub fn mark_drawn(&mut self) {
+        self.needs_redraw = false;
+    }
+
+    pub(crate) fn request_redraw(&mut self) {
+        self.needs_redraw = true;
+    }
+
+    pub fn highlight(&mut self, syntax: &Syntax, word: Option<&str>, options: SearchOptions) {
+        self.highlight_with(syntax, word, &[], options);
+    }
+
+    /// Like `highlight`, but marks every match of each term with the type
+    /// paired with it, e.g. to show several log patterns in different
+    /// colors. Later terms win where matches overlap.
+    pub fn highlight_terms(
+        &mut self,
+        syntax: &Syntax,
+        terms: &[(String, highlighting::Type)],
+        options: SearchOptions,
+    ) {
+        self.highlight_with(syntax, None, terms, options);
+    }
+
+    fn highlight_with(
+        &mut self,
+        syntax: &Syntax,
+        word: Option<&str>,
+        terms: &[(String, highlighting::Type)],
+        options: SearchOptions,
+    ) {
+        if word.is_none() && terms.is_empty() && syntax.is_plain() {
+            // Nothing to mark: `render` treats missing entries as
+            // `Type::None`, so skip allocating a vector on every edit.
+            if !self.highlighting.is_empty() {
+                self.highlighting.clear();
+                self.needs_redraw = true;
+            }
+            return;
+        }
+        let graphemes: Vec<&str> = self.string[..].graphemes(true).collect();
+        let mut highlighting = Vec::with_capacity(graphemes.len());
+        let mut index = 0;
+        if let Some(style) = syntax.meta() {
+            let indent = graphemes
+                .iter()
+                .take_while(|grapheme| grapheme.chars().all(char::is_whitespace))
+                .count();
+            if let Some(len) = meta_len(&graphemes[indent..], style) {
+                highlighting.resize(indent, highlighting::Type::None);
+                highlighting.resize(indent + len, highlighting::Type::Meta);
+                index = indent + len;
+            }
+        }
+        let comment: Vec<&str> = syntax
+            .line_comment()
+            .map(|marker| marker.graphemes(true).collect())
+            .unwrap_or_default();
+        let mut prev_is_separator = true;
+        while let Some(grapheme) = graphemes.get(index) {
+            if syntax.highlight_strings() && *grapheme == "\"" {
+                let len = string_len(&graphemes[index..]);
+                highlighting.resize(index + len, highlighting::Type::String);
+                index += len;
+                prev_is_separator = true;
+                continue;
+            }
+            if !comment.is_empty() && graphemes[index..].starts_with(&comment) {
+                highlight_comment(
+                    &graphemes[index..],
+                    syntax.todo_keywords(),
+                    &mut highlighting,
+                );
+                break;
+            }
+            let previous_highlight = highlighting
+                .last()
+                .copied()
+                .unwrap_or(highlighting::Type::None);
+            let is_digit = syntax.highlight_numbers()
+                && grapheme.len() == 1
+                && grapheme.as_bytes()[0].is_ascii_digit();
+            // A number only starts at a word boundary, so the digits in an
+            // identifier like `user42` stay plain.
+            if (is_digit && (prev_is_separator || previous_highlight == highlighting::Type::Number))
+                || (*grapheme == "." && previous_highlight == highlighting::Type::Number)
+            {
+                highlighting.push(highlighting::Type::Number);
+            } else {
+                highlighting.push(highlighting::Type::None);
+            }
+            prev_is_separator = is_separator(grapheme);
+            index += 1;
+        }
+        if syntax.highlight_markdown() {
+            highlight_markdown(&graphemes, &mut highlighting);
+        }
+        if syntax.line_continuation() {
+            // An even run of trailing backslashes is escaped backslashes,
+            // not a continuation.
+            let backslashes = graphemes
+                .iter()
+                .rev()
+                .take_while(|grapheme| **grapheme == "\\")
+                .count();
+            if backslashes % 2 == 1 {
+                if let Some(last) = highlighting.last_mut() {
+                    *last = highlighting::Type::Continuation;
+                }
+            }
+        }
+        if syntax.highlight_urls() {
+            highlight_urls(&graphemes, &mut highlighting);
+        }
+        if let Some(word) = word {
+            self.highlight_match(&graphemes, word, options, &mut highlighting);
+        }
+        for (term, highlighting_type) in terms {
+            self.highlight_term(term, *highlighting_type, options, &mut highlighting);
+        }
+        if highlighting != self.highlighting {
+            self.highlighting = highlighting;
+            self.needs_redraw = true;
+        }
+    }
+
+    /// Marks every non-overlapping match of `word`, as found by `find_all`,
+    /// with `Type::Search`. When ignoring case, matches whose casing differs
+    /// from `word` get `Type::SearchFuzzy` instead.
+    fn highlight_match(
+        &self,
+        graphemes: &[&str],
+        word: &str,
+        options: SearchOptions,
+        highlighting: &mut [highlighting::Type],
+    ) {
+        let query: Vec<&str> = word.graphemes(true).collect();
+        let options = SearchOptions {
+            skip_overlapping: true,
+            ..options
+        };
+        for index in self.find_all(word, options) {
+            let end = cmp::min(index + query.len(), highlighting.len());
+            let highlighting_type = if graphemes[index..end] == query[..] {
+                highlighting::Type::Search
+            } else {
+                highlighting::Type::SearchFuzzy
+            };
+            highlighting[index..end].fill(highlighting_type);
+        }
+    }
+
+    /// Marks every non-overlapping match of `term`, as found by `find_all`,
+    /// as `highlighting_type`.
+    fn highlight_term(
+        &self,
+        term: &str,
+        highlighting_type: highlighting::Type,
+        options: SearchOptions,
+        highlighting: &mut [highlighting::Type],
+    ) {
+        let len = term.graphemes(true).count();
+        let options = SearchOptions {
+            skip_overlapping: true,
+            ..options
+        };
+        for index in self.find_all(term, options) {
+            let end = cmp::min(index + len, highlighting.len());
+            highlighting[index..end].fill(highlighting_type);
+        }
+    }
+}
+
+/// One display column of rendered output: the grapheme that starts there,
+/// or nothing for the second column of a wide grapheme, with the escapes in
+/// effect for it.
+#[derive(Clone, Default, PartialEq, Eq)]
+struct Cell {
+    text: String,
+    foreground: String,
+    background: String,
+}
+
+/// Splits a rendered string into display columns, tracking the color
+/// escapes in effect at each.
+fn cells(rendered: &str) -> Vec<Cell> {
+    let mut cells: Vec<Cell> = Vec::new();
+    let mut foreground = String::new();
+    let mut background = String::new();
+    let mut escape: Option<String> = None;
+    for grapheme in rendered.graphemes(true) {
+        if let Some(sequence) = &mut escape {
+            sequence.push_str(grapheme);
+            if grapheme == "m" {
+                if sequence.starts_with("\x1b[3") {
+                    foreground = sequence.clone();
+                } else if sequence.starts_with("\x1b[4") {
+                    background = sequence.clone();
+                }
+                escape = None;
+            }
+            continue;
+        }
+        if grapheme == "\x1b" {
+            escape = Some(grapheme.to_string());
+            continue;
+        }
+        let width = grapheme.width();
+        if width == 0 {
+            if let Some(cell) = cells.last_mut() {
+                cell.text.push_str(grapheme);
+            }
+            continue;
+        }
+        let cell = Cell {
+            text: grapheme.to_string(),
+            foreground: foreground.clone(),
+            background: background.clone(),
+        };
+        let placeholder = Cell {
+            text: String::new(),
+            ..cell.clone()
+        };
+        cells.push(cell);
+        cells.extend(std::iter::repeat_n(placeholder, width - 1));
+    }
+    cells
+}
+
+fn is_word_char(grapheme: &str, word_chars: &str) -> bool {
+    grapheme
+        .chars()
+        .any(|c| c.is_alphanumeric() || word_chars.contains(c))
+}
+
+fn is_separator(grapheme: &str) -> bool {
+    !grapheme.chars().any(|c| c.is_alphanumeric() || c == '_')
+}
+
+fn push_highlight(
+    result: &mut String,
+    current_highlighting: &mut highlighting::Type,
+    highlighting_type: highlighting::Type,
+) {
+    if highlighting_type != *current_highlighting {
+        *current_highlighting = highlighting_type;
+        let start_highlight = format!("{}", color::Fg(highlighting_type.to_color()));
+        result.push_str(&start_highlight);
+    }
+}
+
+/// Returns the caret notation (`^G` for BEL, `^?` for DEL) of an ASCII
+/// control character other than tab, so it can't disturb the terminal.
+fn caret_notation(grapheme: &str) -> Option<String> {
+    let mut chars = grapheme.chars();
+    let c = chars.next()?;
+    if chars.next().is_some() || c == '\t' || !c.is_ascii_control() {
+        return None;
+    }
+    Some(format!("^{}", char::from(c as u8 ^ 0x40)))
+}
+
+/// Returns how many columns `grapheme` takes when drawn at column `col`. A
+/// tab advances to the next multiple of `tab_width`, taken as at least 1.
+fn grapheme_width(grapheme: &str, col: usize, tab_width: usize) -> usize {
+    if grapheme == "\t" {
+        let tab_width = cmp::max(tab_width, 1);
+        tab_width - col % tab_width
+    } else if caret_notation(grapheme).is_some() {
+        2
+    } else {
+        grapheme.width()
+    }
+}
+
+/// Marks the rest of the row as a comment, picking out the `keywords` that
+/// stand as whole words within it as `Type::Todo`.
+fn highlight_comment(
+    graphemes: &[&str],
+    keywords: &[String],
+    highlighting: &mut Vec<highlighting::Type>,
+) {
+    let start = highlighting.len();
+    highlighting.resize(start + graphemes.len(), highlighting::Type::Comment);
+    for keyword in keywords {
+        let keyword: Vec<&str> = keyword.graphemes(true).collect();
+        if keyword.is_empty() {
+            continue;
+        }
+        for index in 0..graphemes.len() {
+            let end = index + keyword.len();
+            if graphemes[index..].starts_with(&keyword)
+                && (index == 0 || is_separator(graphemes[index - 1]))
+                && graphemes
+                    .get(end)
+                    .is_none_or(|grapheme| is_separator(grapheme))
+            {
+                highlighting[start + index..start + end].fill(highlighting::Type::Todo);
+            }
+        }
+    }
+}
+
+/// Returns whether `graphemes` starts with `query`, comparing each grapheme
+/// case-insensitively if `options` asks for it.
+fn graphemes_match(graphemes: &[&str], query: &[&str], options: SearchOptions) -> bool {
+    if graphemes.len() < query.len() {
+        return false;
+    }
+    graphemes
+        .iter()
+        .zip(query)
+        .all(|(a, b)| a == b || (options.ignore_case && a.to_lowercase() == b.to_lowercase()))
+}
+
+/// Marks `http://` and `https://` links, from the scheme up to the next
+/// whitespace, as `Type::Url`.
+fn highlight_urls(graphemes: &[&str], highlighting: &mut [highlighting::Type]) {
+    const HTTP: [&str; 7] = ["h", "t", "t", "p", ":", "/", "/"];
+    const HTTPS: [&str; 8] = ["h", "t", "t", "p", "s", ":", "/", "/"];
+    let mut index = 0;
+    while index < graphemes.len() {
+        let rest = &graphemes[index..];
+        if (rest.starts_with(&HTTP) || rest.starts_with(&HTTPS))
+            && (index == 0 || is_separator(graphemes[index - 1]))
+        {
+            let len = rest
+                .iter()
+                .take_while(|grapheme| !grapheme.chars().all(char::is_whitespace))
+                .count();
+            highlighting[index..index + len].fill(highlighting::Type::Url);
+            index += len;
+        } else {
+            index += 1;
+        }
+    }
+}
+
+/// Marks a Markdown ATX heading line as `Type::Heading`, or else inline
+/// `` `code` ``, `**strong**` and `*emphasis*`/`_emphasis_` spans, markers
+/// included.
+fn highlight_markdown(graphemes: &[&str], highlighting: &mut [highlighting::Type]) {
+    let indent = graphemes
+        .iter()
+        .take_while(|grapheme| **grapheme == " ")
+        .count();
+    let hashes = graphemes[indent..]
+        .iter()
+        .take_while(|grapheme| **grapheme == "#")
+        .count();
+    let after_hashes = graphemes.get(indent + hashes);
+    if indent <= 3
+        && (1..=6).contains(&hashes)
+        && after_hashes.is_none_or(|grapheme| *grapheme == " ")
+    {
+        highlighting.fill(highlighting::Type::Heading);
+        return;
+    }
+    let mut index = 0;
+    while index < graphemes.len() {
+        let rest = &graphemes[index..];
+        let (marker, highlighting_type) = match rest {
+            ["`", ..] => ("`", highlighting::Type::Code),
+            ["*", "*", ..] => ("**", highlighting::Type::Strong),
+            ["*", ..] => ("*", highlighting::Type::Emphasis),
+            ["_", ..] if index == 0 || is_separator(graphemes[index - 1]) => {
+                ("_", highlighting::Type::Emphasis)
+            }
+            _ => {
+                index += 1;
+                continue;
+            }
+        };
+        let marker: Vec<&str> = marker.graphemes(true).collect();
+        let content = &rest[marker.len()..];
+        // Emphasis has to hug its text, so `* item` and `a * b` stay plain.
+        let opens = highlighting_type == highlighting::Type::Code
+            || content
+                .first()
+                .is_some_and(|grapheme| !grapheme.trim().is_empty());
+        let close = content
+            .windows(marker.len())
+            .enumerate()
+            .skip(1)
+            .find(|(_, window)| *window == &marker[..])
+            .map(|(offset, _)| index + marker.len() + offset + marker.len());
+        match close {
+            Some(end) if opens => {
+                highlighting[index..end].fill(highlighting_type);
+                index = end;
+            }
+            _ => index += marker.len(),
+        }
+    }
+}
+
+/// Returns how many graphemes the double-quoted string at the start of
+/// `graphemes` spans, closing quote included. An unterminated string runs to
+/// the end of the row.
+fn string_len(graphemes: &[&str]) -> usize {
+    let mut escaped = false;
+    for (index, grapheme) in graphemes.iter().enumerate().skip(1) {
+        match *grapheme {
+            "\"" if !escaped => return index + 1,
+            "\\" => escaped = !escaped,
+            _ => escaped = false,
+        }
+    }
+    graphemes.len()
+}
+
+/// Returns how many graphemes at the start of `graphemes` form a meta span
+/// (an attribute or a preprocessor directive), if any.
+fn meta_len(graphemes: &[&str], style: MetaStyle) -> Option<usize> {
+    if graphemes.first() != Some(&"#") {
+        return None;
+    }
+    match style {
+        MetaStyle::Directive => Some(graphemes.len()),
+        MetaStyle::Attribute => {
+            let open = match graphemes.get(1) {
+                Some(&"[") => 1,
+                Some(&"!") if graphemes.get(2) == Some(&"[") => 2,
+                _ => return None,
+            };
+            let mut depth = 0;
+            for (index, grapheme) in graphemes.iter().enumerate().skip(open) {
+                match *grapheme {
+                    "[" => depth += 1,
+                    "]" => {
+                        depth -= 1;
+                        if depth == 0 {
+                            return Some(index + 1);
+                        }
+                    }
+                    _ => (),
+                }
+            }
+            Some(graphemes.len())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::highlighting::Type;
+
+    fn highlighted(text: &str, filename: &str) -> Row {
+        let mut row = Row::from(text);
+        row.highlight(
+            &Syntax::from_filename(filename),
+            None,
+            SearchOptions::default(),
+        );
+        row
+    }
+
+    #[test]
+    fn rust_attribute_is_meta() {
+        let row = highlighted("#[derive(Debug)]", "a.rs");
+        assert!(row.highlighting().iter().all(|t| *t == Type::Meta));
+        let row = highlighted("  #![allow(x[0])] fn 1", "a.rs");
+        assert_eq!(row.highlighting()[2..17], [Type::Meta; 15]);
+        assert_eq!(row.highlighting()[17], Type::None);
+    }
+
+    #[test]
+    fn c_directive_is_meta() {
+        let row = highlighted("#include <a>", "a.c");
+        assert!(row.highlighting().iter().all(|t| *t == Type::Meta));
+    }
+
+    #[test]
+    fn shell_hash_is_a_comment_not_meta() {
+        let row = highlighted("# comment 1", "a.sh");
+        assert_eq!(row.highlighting()[0], Type::Comment);
+    }
+
+    #[test]
+    fn case_insensitive_matches_are_fuzzy_unless_exact() {
+        let mut row = Row::from("Error error");
+        let options = SearchOptions {
+            ignore_case: true,
+            ..SearchOptions::default()
+        };
+        row.highlight(&Syntax::default(), Some("error"), options);
+        assert_eq!(row.highlighting()[0..5], [Type::SearchFuzzy; 5]);
+        assert_eq!(row.highlighting()[5], Type::None);
+        assert_eq!(row.highlighting()[6..11], [Type::Search; 5]);
+        row.highlight(&Syntax::default(), Some("error"), SearchOptions::default());
+        assert_eq!(row.highlighting()[0..5], [Type::None; 5]);
+    }
+
+    /// Strips the ANSI escapes from rendered output, leaving the text.
+    fn plain(rendered: &str) -> String {
+        let mut text = String::new();
+        let mut chars = rendered.chars();
+        while let Some(c) = chars.next() {
+            if c == '\x1b' {
+                chars.by_ref().find(|&c| c == 'm');
+            } else {
+                text.push(c);
+            }
+        }
+        text
+    }
+
+    #[test]
+    fn render_cols_pads_a_wide_character_cut_by_the_left_edge() {
+        let row = Row::from("a日本b");
+        assert_eq!(plain(&row.render_cols(0, 10, 4)), "a日本b");
+        assert_eq!(plain(&row.render_cols(2, 10, 4)), " 本b");
+        assert_eq!(plain(&row.render_cols(1, 4, 4)), "日 ");
+        assert_eq!(row.rx_to_cx(2, 4), 1);
+        assert_eq!(row.cx_to_rx(2, 4), 3);
+    }
+
+    #[test]
+    fn digits_inside_identifiers_are_not_numbers() {
+        let row = highlighted("user42 x = 42 a_1 3.14", "a.rs");
+        let highlighting = row.highlighting();
+        assert!(highlighting[0..6].iter().all(|t| *t == Type::None));
+        assert_eq!(highlighting[11..13], [Type::Number; 2]);
+        assert_eq!(highlighting[16], Type::None);
+        assert_eq!(highlighting[18..22], [Type::Number; 4]);
+    }
+
+    #[test]
+    fn todo_keywords_stand_out_inside_comments() {
+        let rust = Syntax::from_filename("a.rs");
+        let mut row = Row::from("x // TODO: fix this TODOS");
+        row.highlight(&rust, None, SearchOptions::default());
+        let highlighting = row.highlighting();
+        assert_eq!(highlighting[0], Type::None);
+        assert_eq!(highlighting[2..5], [Type::Comment; 3]);
+        assert_eq!(highlighting[5..9], [Type::Todo; 4]);
+        assert_eq!(highlighting[9..], [Type::Comment; 16]);
+        let row = highlighted("echo 1 # XXX", "a.sh");
+        assert_eq!(row.highlighting()[5], Type::Number);
+        assert_eq!(row.highlighting()[9..], [Type::Todo; 3]);
+    }
+
+    #[test]
+    fn todo_keywords_are_configurable() {
+        let mut syntax = Syntax::from_filename("a.rs");
+        syntax.set_todo_keywords(vec![String::from("fix")]);
+        let mut row = Row::from("x // TODO: fix this");
+        row.highlight(&syntax, None, SearchOptions::default());
+        assert_eq!(row.highlighting()[5], Type::Comment);
+        assert_eq!(row.highlighting()[11..14], [Type::Todo; 3]);
+    }
+
+    #[test]
+    fn render_counts_graphemes_not_bytes() {
+        let row = Row::from("日本語テキスト");
+        assert_eq!(plain(&row.render(0, 3)), "日本語");
+        assert_eq!(plain(&row.render(5, 100)), "スト");
+    }
+
+    #[test]
+    fn leading_indent_reports_tabs_spaces_and_mixes() {
+        assert_eq!(Row::from("\t\tx").leading_indent(), (2, IndentKind::Tabs));
+        assert_eq!(Row::from("    x").leading_indent(), (4, IndentKind::Spaces));
+        assert_eq!(Row::from(" \t x").leading_indent(), (3, IndentKind::Mixed));
+        assert_eq!(Row::from("x ").leading_indent(), (0, IndentKind::None));
+    }
+
+    #[test]
+    fn split_on_keeps_the_delimiter_with_each_piece() {
+        assert_eq!(
+            Row::from(r#"{"a":1,"b":2,"c":3}"#).split_on(','),
+            [r#"{"a":1,"#, r#""b":2,"#, r#""c":3}"#]
+        );
+        assert!(Row::from("").split_on(',').is_empty());
+    }
+
+    #[test]
+    fn tabs_advance_to_the_next_tab_stop() {
+        let row = Row::from("ab\tc\td");
+        assert_eq!(plain(&row.render(0, 10)), "ab  c   d");
+        assert_eq!(plain(&row.render_cols(0, 10, 4)), "ab  c   d");
+        assert_eq!(row.cx_to_rx(3, 4), 4);
+        assert_eq!(row.rx_to_cx(3, 4), 2);
+        assert_eq!(row.rx_to_cx(4, 4), 3);
+        assert_eq!(plain(&row.render_cols(3, 6, 4)), " c ");
+    }
+
+    #[test]
+    fn zero_tab_width_counts_tabs_as_one_column() {
+        let row = Row::from("a\tb");
+        assert_eq!(row.width(0), 3);
+        assert_eq!(plain(&row.render_cols(0, 10, 0)), "a b");
+        let options = RenderOptions {
+            tab_width: 0,
+            ..RenderOptions::default()
+        };
+        assert_eq!(plain(&row.render_with(0, 10, &options)), "a b");
+    }
+
+    #[test]
+    fn find_skips_matches_starting_inside_a_grapheme() {
+        let row = Row::from("日a日a e\u{301}x ex");
+        let forward = SearchDirection::Forward;
+        let backward = SearchDirection::Backward;
+        assert_eq!(row.find("a", 0, forward), Some(1));
+        assert_eq!(row.find("a", 2, forward), Some(3));
+        assert_eq!(row.find("a", 4, backward), Some(3));
+        assert_eq!(row.find("a", 3, backward), Some(1));
+        assert_eq!(row.find("a", 1, backward), None);
+        assert_eq!(row.find("\u{301}x", 0, forward), None);
+        assert_eq!(row.find("ex", 0, forward), Some(8));
+        assert_eq!(row.find("ex", 10, backward), Some(8));
+        assert_eq!(row.find("ex", 9, backward), None);
+        assert_eq!(row.find("a", 11, forward), None);
+        let row = Row::from("ab\u{301} ab e\u{301}");
+        assert_eq!(row.find("ab", 0, forward), Some(3));
+        assert_eq!(row.find("ab", 5, backward), Some(3));
+        assert_eq!(row.find("ab", 3, backward), None);
+        assert_eq!(row.find("e", 0, forward), None);
+        assert_eq!(row.find("b\u{301}", 0, forward), Some(1));
+    }
+
+    #[test]
+    fn split_keeps_every_byte_on_one_side() {
+        for at in [0, 3, 4, 9] {
+            let mut row = Row::from("日e\u{301}ab");
+            let rest = row.split(at);
+            assert_eq!(format!("{}{}", row.as_str(), rest.as_str()), "日e\u{301}ab");
+            assert_eq!(row.len(), at.min(4));
+            assert_eq!(row.len() + rest.len(), 4);
+        }
+    }
+
+    #[test]
+    fn control_characters_render_in_caret_notation() {
+        let row = Row::from("a\x07b\x7f");
+        assert_eq!(plain(&row.render(0, 10)), "a^Gb^?");
+        assert!(!row.render(0, 10).contains('\x07'));
+        assert_eq!(row.render_spans(0, 10)[1].highlighting, Type::Control);
+        assert_eq!(plain(&row.render_cols(0, 10, 4)), "a^Gb^?");
+        assert_eq!(plain(&row.render_cols(2, 10, 4)), " b^?");
+        assert_eq!(row.cx_to_rx(2, 4), 3);
+    }
+
+    #[test]
+    fn render_with_pads_the_left_edge() {
+        let row = highlighted("42", "a.rs");
+        let options = RenderOptions {
+            left_pad: 3,
+            ..RenderOptions::default()
+        };
+        let rendered = row.render_with(0, 9, &options);
+        assert!(rendered.starts_with("   \x1b["));
+        assert_eq!(plain(&rendered), "   42");
+        let options = RenderOptions {
+            tab_width: 2,
+            left_pad: 0,
+            ..RenderOptions::default()
+        };
+        assert_eq!(plain(&Row::from("\tx").render_with(0, 9, &options)), "  x");
+    }
+
+    #[test]
+    fn urls_are_highlighted_when_enabled() {
+        let mut syntax = Syntax::default();
+        syntax.set_highlight_urls(true);
+        let mut row = Row::from("see https://x.io/a?b=1 now xhttp://no");
+        row.highlight(&syntax, None, SearchOptions::default());
+        let highlighting = row.highlighting();
+        assert_eq!(highlighting[3], Type::None);
+        assert_eq!(highlighting[4..22], [Type::Url; 18]);
+        assert_eq!(highlighting[22], Type::None);
+        assert!(highlighting[27..].iter().all(|t| *t == Type::None));
+        let mut rust = Syntax::from_filename("a.rs");
+        rust.set_highlight_urls(true);
+        let mut row = Row::from("// http://a.b");
+        row.highlight(&rust, None, SearchOptions::default());
+        assert_eq!(row.highlighting()[3..], [Type::Url; 10]);
+    }
+
+    #[test]
+    fn graphemes_iterate_user_perceived_characters() {
+        let row = Row::from("a日e\u{301}本b");
+        assert_eq!(
+            row.graphemes().collect::<Vec<_>>(),
+            ["a", "日", "e\u{301}", "本", "b"]
+        );
+        assert_eq!(row.grapheme_indices().nth(3), Some((3, "本")));
+    }
+
+    #[test]
+    fn word_spans_report_grapheme_starts_and_lengths() {
+        let row = Row::from("Héllo, wörld! it's 日本");
+        let spans = row.word_spans();
+        assert_eq!(spans[0], (0, 5, "Héllo".to_string()));
+        assert_eq!(spans[1], (7, 5, "wörld".to_string()));
+        assert_eq!(spans[2], (14, 4, "it's".to_string()));
+        assert_eq!(spans[3].0, 19);
+    }
+
+    #[test]
+    fn leading_and_interior_tabs_get_their_own_background() {
+        let options = RenderOptions {
+            leading_tab: Some(Type::Comment),
+            interior_tab: Some(Type::Control),
+            ..RenderOptions::default()
+        };
+        let rendered = Row::from("\t\tfoo\tbar").render_with(0, 20, &options);
+        let leading = format!("{}", color::Bg(Type::Comment.to_color()));
+        let interior = format!("{}", color::Bg(Type::Control.to_color()));
+        let reset = format!("{}", color::Bg(color::Reset));
+        let expected = format!("{leading}        {reset}foo{interior} {reset}bar");
+        assert!(rendered.contains(&expected), "{rendered:?}");
+        assert_eq!(plain(&rendered), "        foo bar");
+        assert!(!Row::from("\tx").render(0, 5).contains(&reset));
+    }
+
+    #[test]
+    fn prefix_checks_see_the_raw_row() {
+        let row = Row::from("  \t// note;");
+        assert!(!row.starts_with("//"));
+        assert!(row.starts_with("  \t"));
+        assert!(row.trimmed_start().starts_with("//"));
+        assert!(row.ends_with(";"));
+        let row = Row::from("e\u{301}x a\u{301}");
+        assert!(!row.starts_with("e"));
+        assert!(row.starts_with("e\u{301}"));
+        assert!(!row.ends_with("\u{301}"));
+        assert!(row.ends_with(" a\u{301}"));
+        assert_eq!(Row::from(" \u{301}x").trimmed_start(), " \u{301}x");
+        assert_eq!(Row::from("\t x").trimmed_start(), "x");
+        assert_eq!(Row::from("  ").trimmed_start(), "");
+    }
+
+    #[test]
+    fn render_with_map_maps_each_column_to_its_grapheme() {
+        let row = Row::from("a\t日b");
+        let (rendered, map) = row.render_with_map(0, 10, 4);
+        assert_eq!(plain(&rendered), "a   日b");
+        assert_eq!(map, [0, 1, 1, 1, 2, 2, 3]);
+        let (_, map) = row.render_with_map(2, 10, 4);
+        assert_eq!(map, [2, 2, 3]);
+    }
+
+    #[test]
+    fn indent_guides_mark_each_indent_level() {
+        let options = RenderOptions {
+            indent_guides: true,
+            ..RenderOptions::default()
+        };
+        let rendered = Row::from("        x  y").render_with(0, 20, &options);
+        let guide = format!("{}", color::Bg(Type::IndentGuide.to_color()));
+        let reset = format!("{}", color::Bg(color::Reset));
+        let expected = format!("{guide} {reset}   {guide} {reset}   x  y");
+        assert!(rendered.contains(&expected), "{rendered:?}");
+        assert!(!Row::from("        x").render(0, 20).contains(&guide));
+    }
+
+    #[test]
+    fn wrap_breaks_rows_at_the_screen_width() {
+        assert_eq!(
+            Row::from("abcdefghij").wrap(4, 4),
+            [(0, 4), (4, 8), (8, 10)]
+        );
+        assert_eq!(Row::from("").wrap(4, 4), [(0, 0)]);
+        assert_eq!(Row::from("ab日c").wrap(3, 4), [(0, 2), (2, 4)]);
+    }
+
+    #[test]
+    fn find_all_agrees_with_find() {
+        let row = Row::from("e\u{301}x ex");
+        let options = SearchOptions::default();
+        assert_eq!(row.find("e", 0, SearchDirection::Forward), Some(3));
+        assert_eq!(row.find_all("e", options), [3]);
+        assert_eq!(row.find_all("ex", options), [3]);
+        let row = Row::from("aaa");
+        assert_eq!(row.find_all("aa", options), [0, 1]);
+        let skip = SearchOptions {
+            skip_overlapping: true,
+            ..SearchOptions::default()
+        };
+        assert_eq!(row.find_all("aa", skip), [0]);
+        assert!(row.find_all("", skip).is_empty());
+        let ignore_case = SearchOptions {
+            ignore_case: true,
+            ..SearchOptions::default()
+        };
+        assert_eq!(Row::from("AbaBab").find_all("ab", ignore_case), [0, 2, 4]);
+    }
+
+    #[test]
+    fn insert_padded_fills_the_gap_with_spaces() {
+        let mut row = Row::from("abc");
+        row.insert_padded(10, 'x');
+        assert_eq!(row.as_str(), "abc       x");
+        assert_eq!(row.len(), 11);
+        row.insert_padded(1, '-');
+        assert_eq!(row.as_str(), "a-bc       x");
+    }
+
+    #[test]
+    fn find_char_jumps_to_the_nearest_grapheme() {
+        let row = Row::from("a,b,c日,");
+        assert_eq!(row.find_char(0, ',', true), Some(1));
+        assert_eq!(row.find_char(1, ',', true), Some(3));
+        assert_eq!(row.find_char(3, ',', true), Some(6));
+        assert_eq!(row.find_char(6, ',', true), None);
+        assert_eq!(row.find_char(6, ',', false), Some(3));
+        assert_eq!(row.find_char(99, '日', false), Some(5));
+        assert_eq!(row.find_char(1, ',', false), None);
+        assert_eq!(row.find_char(0, 'z', true), None);
+    }
+
+    #[test]
+    fn a_trailing_backslash_is_a_shell_continuation() {
+        let row = highlighted("echo foo \\", "a.sh");
+        assert_eq!(row.highlighting()[9], Type::Continuation);
+        assert!(!row.highlighting()[..9].contains(&Type::Continuation));
+        for text in ["echo \\ foo", "echo \\\\"] {
+            let row = highlighted(text, "a.sh");
+            assert!(!row.highlighting().contains(&Type::Continuation), "{text}");
+        }
+        let row = highlighted("x \\", "a.rs");
+        assert!(!row.highlighting().contains(&Type::Continuation));
+    }
+
+    #[test]
+    fn edits_flag_a_row_for_redraw() {
+        let mut row = Row::from("q");
+        row.mark_drawn();
+        assert!(!row.needs_redraw());
+        row.insert(0, 'z');
+        assert!(row.needs_redraw());
+    }
+
+    #[test]
+    fn render_diff_patches_only_changed_columns() {
+        let syntax = Syntax::from_filename("a.rs");
+        let mut row = Row::from("let x = 1; 日本");
+        row.highlight(&syntax, None, SearchOptions::default());
+        let previous = row.render(0, 40);
+        assert!(row.render_diff(&previous, 0, 40, 4).is_empty());
+        row.delete(4);
+        row.insert(4, 'y');
+        row.highlight(&syntax, None, SearchOptions::default());
+        let patches = row.render_diff(&previous, 0, 40, 4);
+        assert_eq!(patches.len(), 1);
+        assert_eq!(patches[0].0, 4);
+        assert_eq!(plain(&patches[0].1), "y");
+        row.delete(0);
+        row.highlight(&syntax, None, SearchOptions::default());
+        let patches = row.render_diff(&previous, 0, 40, 4);
+        assert_eq!(patches[0].0, 0);
+        assert_eq!(plain(&patches[0].1), "et y = 1; 日本 ");
+        let mut wide = Row::from("日本");
+        let previous = wide.render(0, 9);
+        wide.delete(1);
+        wide.insert(1, '人');
+        assert_eq!(wide.render_diff(&previous, 0, 9, 4)[0].0, 2);
+    }
+
+    #[test]
+    fn codepoint_indices_map_to_graphemes() {
+        let row = Row::from("e\u{301}x");
+        assert_eq!(row.len_in(CursorModel::Grapheme), 2);
+        assert_eq!(row.len_in(CursorModel::Codepoint), 3);
+        assert_eq!(row.from_model_index(1, CursorModel::Codepoint), 0);
+        assert_eq!(row.to_model_index(1, CursorModel::Codepoint), 2);
+    }
+
+    #[test]
+    fn an_unterminated_string_runs_to_the_end_of_the_row() {
+        let row = highlighted("x = \"unterminated", "a.py");
+        assert_eq!(row.highlighting()[16], Type::String);
+    }
+
+    #[test]
+    fn grapheme_width_at_accounts_for_tabs_and_wide_characters() {
+        let row = Row::from("\t日a\t");
+        assert_eq!(row.grapheme_width_at(0, 4), 4);
+        assert_eq!(row.grapheme_width_at(1, 4), 2);
+        assert_eq!(row.grapheme_width_at(2, 4), 1);
+        assert_eq!(row.grapheme_width_at(3, 4), 1);
+        assert_eq!(row.grapheme_width_at(9, 4), 1);
+    }
+
+    #[test]
+    fn flags_are_a_single_grapheme() {
+        let row = Row::from("a🇩🇪b");
+        assert_eq!(row.grapheme_at(1), Some("🇩🇪"));
+        assert_eq!(row.grapheme_at(3), None);
+        assert_eq!(plain(&row.render(0, 9)), "a🇩🇪b");
+        assert_eq!(plain(&row.render(1, 2)), "🇩🇪");
+    }
+
+    #[test]
+    fn render_keeps_combining_marks_and_joiners_byte_exact() {
+        for text in ["cafe\u{301}", "e\u{301}\u{323}x", "👩\u{200d}💻 a"] {
+            assert_eq!(plain(&Row::from(text).render(0, 99)), text);
+        }
+    }
+
+    #[test]
+    fn markdown_marks_headings_and_inline_spans() {
+        let markdown = Syntax::from_filename("README.md");
+        assert_eq!(markdown.name(), "Markdown");
+        let highlight = |text: &str| {
+            let mut row = Row::from(text);
+            row.highlight(&markdown, None, SearchOptions::default());
+            row.highlighting().to_vec()
+        };
+        assert!(highlight("## Title").iter().all(|t| *t == Type::Heading));
+        assert!(highlight("#hashtag").iter().all(|t| *t == Type::None));
+        let spans = highlight("a **bold** and *it* `x*y` snake_case_name * item");
+        assert_eq!(spans[1], Type::None);
+        assert_eq!(spans[2..10], [Type::Strong; 8]);
+        assert_eq!(spans[15..19], [Type::Emphasis; 4]);
+        assert_eq!(spans[20..25], [Type::Code; 5]);
+        assert!(spans[25..].iter().all(|t| *t == Type::None));
+        let row = highlighted("**bold**", "a.rs");
+        assert!(!row.highlighting().contains(&Type::Strong));
+    }
+
+    #[test]
+    fn pad_to_fills_up_to_a_display_width() {
+        let mut row = Row::from("日本");
+        row.pad_to(6, 4);
+        assert_eq!(row.as_str(), "日本  ");
+        assert_eq!(row.len(), 4);
+        let mut row = Row::from("abcdef");
+        row.pad_to(3, 4);
+        assert_eq!(row.as_str(), "abcdef");
+        let mut row = Row::from("\t");
+        row.pad_to(6, 4);
+        assert_eq!(row.as_str(), "\t  ");
+    }
+
+    #[test]
+    fn replace_range_clamps_to_the_row() {
+        let mut row = Row::from("héllo world");
+        row.replace_range(1, 5, "EEEEEE");
+        assert_eq!(row.as_str(), "hEEEEEE world");
+        assert_eq!(row.len(), 13);
+        row.replace_range(1, 7, "i");
+        assert_eq!(row.as_str(), "hi world");
+        row.replace_range(6, 99, "!");
+        assert_eq!(row.as_str(), "hi wor!");
+        row.replace_range(50, 60, "?");
+        assert_eq!(row.as_str(), "hi wor!?");
+        assert_eq!(row.len(), 8);
+    }
+
+    #[test]
+    fn byte_offsets_round_to_grapheme_boundaries() {
+        let row = Row::from("aé日e\u{301}z");
+        assert_eq!(row.grapheme_to_byte(0), 0);
+        assert_eq!(row.grapheme_to_byte(2), 3);
+        assert_eq!(row.grapheme_to_byte(3), 6);
+        assert_eq!(row.grapheme_to_byte(4), 9);
+        assert_eq!(row.grapheme_to_byte(99), 10);
+        assert_eq!(row.byte_to_grapheme(1), 1);
+        assert_eq!(row.byte_to_grapheme(2), 1);
+        assert_eq!(row.byte_to_grapheme(4), 2);
+        assert_eq!(row.byte_to_grapheme(7), 3);
+        assert_eq!(row.byte_to_grapheme(9), 4);
+        assert_eq!(row.byte_to_grapheme(10), 5);
+    }
+
+    #[test]
+    fn search_highlighting_marks_what_find_all_finds() {
+        let mut row = Row::from("ab\u{301} ab AB");
+        let options = SearchOptions::default();
+        row.highlight(&Syntax::default(), Some("ab"), options);
+        let found = row.find_all("ab", options);
+        assert_eq!(found, [3]);
+        assert_eq!(row.highlighting()[3..5], [Type::Search; 2]);
+        assert!(!row.highlighting()[..3].contains(&Type::Search));
+        let ignore_case = SearchOptions {
+            ignore_case: true,
+            ..SearchOptions::default()
+        };
+        row.highlight(&Syntax::default(), Some("ab"), ignore_case);
+        assert_eq!(row.highlighting()[6..8], [Type::SearchFuzzy; 2]);
+        row.highlight_terms(&Syntax::default(), &[("b".into(), Type::Todo)], options);
+        assert_eq!(row.highlighting()[4], Type::Todo);
+        assert_eq!(row.highlighting()[1], Type::None);
+    }
+}
@@ -0,0 +1,160 @@
+use crate::{Document, Match, Position, SearchOptions};
+use unicode_segmentation::UnicodeSegmentation;
+
+/// One entry of a results list: where the match starts, the text of its
+/// row, and how many graphemes it spans.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SearchResult {
+    pub position: Position,
+    pub line: String,
+    pub match_len: usize,
+}
+
+/// The matches of an incremental search. Typing more of the query only
+/// filters the cached matches instead of scanning the whole document again.
+///
+/// The cache knows nothing about edits, so call `clear` whenever the
+/// document changes.
+#[derive(Default, Clone, Debug)]
+pub struct SearchState {
+    query: String,
+    options: SearchOptions,
+    matches: Vec<Match>,
+}
+
+impl SearchState {
+    /// Searches `document` for `query` and returns every match, in document
+    /// order. Matches may overlap unless `options.skip_overlapping` is set.
+    pub fn update(&mut self, document: &Document, query: &str, options: SearchOptions) -> &[Match] {
+        if query == self.query && options == self.options {
+            return &self.matches;
+        }
+        // Skipping overlaps depends on the whole query, so a longer one can
+        // match where a cached match was skipped.
+        let narrows = !options.skip_overlapping
+            && !self.query.is_empty()
+            && query.starts_with(self.query.as_str())
+            && options == self.options
+            && extends_last_grapheme_unchanged(&self.query, query);
+        if narrows {
+            let len = query.graphemes(true).count();
+            self.matches.retain_mut(|found| {
+                found.len = len;
+                document
+                    .row(found.start.y)
+                    .is_some_and(|row| row.matches_at(query, found.start.x, options))
+            });
+        } else {
+            self.matches = scan(document, query, options);
+        }
+        self.query = query.to_string();
+        self.options = options;
+        &self.matches
+    }
+
+    pub fn query(&self) -> &str {
+        &self.query
+    }
+
+    pub fn matches(&self) -> &[Match] {
+        &self.matches
+    }
+
+    pub fn clear(&mut self) {
+        *self = Self::default();
+    }
+}
+
+/// Whether `query`, which starts with `old`, keeps the last grapheme of
+/// `old` as it is. A combining mark typed after it joins that grapheme
+/// instead, and the cached matches of the bare base can miss the new one.
+fn extends_last_grapheme_unchanged(old: &str, query: &str) -> bool {
+    let count = old.graphemes(true).count();
+    query.graphemes(true).nth(count - 1) == old.graphemes(true).next_back()
+}
+
+fn scan(document: &Document, query: &str, options: SearchOptions) -> Vec<Match> {
+    let len = query.graphemes(true).count();
+    document
+        .matches(query, options)
+        .map(|start| Match { start, len })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn narrowing_matches_a_fresh_scan() {
+        let document = Document::from_str("abc abd\nxab ABC\nab\n");
+        let mut state = SearchState::default();
+        let options = SearchOptions {
+            ignore_case: true,
+            ..SearchOptions::default()
+        };
+        assert_eq!(state.update(&document, "a", options).len(), 5);
+        for query in ["ab", "abc", "ab", "b", ""] {
+            let narrowed = state.update(&document, query, options).to_vec();
+            let fresh = SearchState::default()
+                .update(&document, query, options)
+                .to_vec();
+            assert_eq!(narrowed, fresh, "{query}");
+        }
+        assert_eq!(
+            SearchState::default()
+                .update(&document, "abc", options)
+                .len(),
+            2
+        );
+        let all = SearchState::default()
+            .update(&Document::from_str("aaa"), "aa", options)
+            .to_vec();
+        assert_eq!(all.len(), 2);
+    }
+
+    #[test]
+    fn a_combining_mark_rescans_instead_of_narrowing() {
+        let document = Document::from_str("e\u{301}x ex\n");
+        let options = SearchOptions::default();
+        let mut state = SearchState::default();
+        state.update(&document, "e", options);
+        let narrowed = state.update(&document, "e\u{301}", options).to_vec();
+        let fresh = SearchState::default()
+            .update(&document, "e\u{301}", options)
+            .to_vec();
+        assert_eq!(narrowed, fresh);
+        assert_eq!(narrowed.len(), 1);
+    }
+
+    #[test]
+    fn narrowing_uses_the_same_matcher_as_a_scan() {
+        let document = Document::from_str("ab\u{301} ab\n");
+        let options = SearchOptions::default();
+        let mut state = SearchState::default();
+        state.update(&document, "a", options);
+        let narrowed = state.update(&document, "ab", options).to_vec();
+        let fresh = SearchState::default()
+            .update(&document, "ab", options)
+            .to_vec();
+        assert_eq!(narrowed, fresh);
+        assert_eq!(narrowed.len(), 1);
+    }
+
+    #[test]
+    fn skipping_overlaps_always_rescans() {
+        let document = Document::from_str("aaaa\n");
+        let skip = SearchOptions {
+            skip_overlapping: true,
+            ..SearchOptions::default()
+        };
+        let mut state = SearchState::default();
+        assert_eq!(state.update(&document, "a", skip).len(), 4);
+        let narrowed = state.update(&document, "aa", skip).to_vec();
+        assert_eq!(
+            narrowed,
+            SearchState::default().update(&document, "aa", skip)
+        );
+        assert_eq!(narrowed.len(), 2);
+    }
+}
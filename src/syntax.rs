@@ -0,0 +1,291 @@
+use std::ffi::OsStr;
+use std::path::Path;
+
+/// How a language marks lines that are metadata rather than code.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MetaStyle {
+    /// Rust-style `#[...]` and `#![...]` attributes, up to the matching bracket.
+    Attribute,
+    /// C-style `#include`/`#define` directives, up to the end of the line.
+    Directive,
+}
+
+/// What kind of text a position is in, judging by its highlighting.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SyntaxContext {
+    Code,
+    String,
+    Comment,
+}
+
+const DEFAULT_TODO_KEYWORDS: [&str; 3] = ["TODO", "FIXME", "XXX"];
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Syntax {
+    name: String,
+    meta: Option<MetaStyle>,
+    highlight_numbers: bool,
+    line_comment: Option<String>,
+    todo_keywords: Vec<String>,
+    highlight_urls: bool,
+    line_continuation: bool,
+    highlight_strings: bool,
+    highlight_markdown: bool,
+    auto_pairs: Vec<(String, String)>,
+}
+
+impl Default for Syntax {
+    fn default() -> Self {
+        Self {
+            name: String::from("No filetype"),
+            meta: None,
+            highlight_numbers: false,
+            line_comment: None,
+            todo_keywords: DEFAULT_TODO_KEYWORDS
+                .iter()
+                .map(ToString::to_string)
+                .collect(),
+            highlight_urls: false,
+            line_continuation: false,
+            highlight_strings: false,
+            highlight_markdown: false,
+            auto_pairs: Vec::new(),
+        }
+    }
+}
+
+impl Syntax {
+    pub fn from_filename(filename: &str) -> Self {
+        let extension = Path::new(filename)
+            .extension()
+            .and_then(OsStr::to_str)
+            .unwrap_or("");
+        match extension {
+            "rs" => Self::rust(),
+            "c" | "h" | "cc" | "cpp" | "hpp" => Self::c(),
+            "sh" | "bash" => Self::shell(),
+            "py" => Self::python(),
+            "md" | "markdown" => Self::markdown(),
+            _ => Self::default(),
+        }
+    }
+
+    /// Picks a syntax from a `#!` line, e.g. `#!/usr/bin/env python3`.
+    pub fn for_content(first_line: &str) -> Option<Self> {
+        let mut parts = first_line.strip_prefix("#!")?.split_whitespace();
+        let mut program = parts.next()?.rsplit('/').next()?;
+        if program == "env" {
+            program = parts.find(|part| !part.starts_with('-'))?;
+        }
+        let interpreter = program.trim_end_matches(|c: char| c.is_ascii_digit() || c == '.');
+        match interpreter {
+            "sh" | "bash" | "dash" | "ksh" | "zsh" => Some(Self::shell()),
+            "python" => Some(Self::python()),
+            _ => None,
+        }
+    }
+
+    /// Picks a syntax from a Vim or Emacs modeline, e.g. `// vim: set ft=rust:`
+    /// or `# -*- mode: python -*-`.
+    pub fn for_modeline(line: &str) -> Option<Self> {
+        Self::from_language(vim_filetype(line).or_else(|| emacs_mode(line))?)
+    }
+
+    fn from_language(language: &str) -> Option<Self> {
+        match language.to_ascii_lowercase().as_str() {
+            "rust" => Some(Self::rust()),
+            "c" | "cpp" | "c++" => Some(Self::c()),
+            "sh" | "bash" | "zsh" | "shell" | "shell-script" => Some(Self::shell()),
+            "python" => Some(Self::python()),
+            "markdown" | "md" => Some(Self::markdown()),
+            _ => None,
+        }
+    }
+
+    fn rust() -> Self {
+        Self {
+            name: String::from("Rust"),
+            meta: Some(MetaStyle::Attribute),
+            highlight_numbers: true,
+            highlight_strings: true,
+            line_comment: Some(String::from("//")),
+            auto_pairs: block_comment_pairs(),
+            ..Self::default()
+        }
+    }
+
+    fn c() -> Self {
+        Self {
+            name: String::from("C"),
+            meta: Some(MetaStyle::Directive),
+            highlight_numbers: true,
+            highlight_strings: true,
+            line_comment: Some(String::from("//")),
+            line_continuation: true,
+            auto_pairs: block_comment_pairs(),
+            ..Self::default()
+        }
+    }
+
+    fn shell() -> Self {
+        Self {
+            name: String::from("Shell"),
+            highlight_numbers: true,
+            highlight_strings: true,
+            line_comment: Some(String::from("#")),
+            line_continuation: true,
+            ..Self::default()
+        }
+    }
+
+    fn python() -> Self {
+        Self {
+            name: String::from("Python"),
+            highlight_numbers: true,
+            highlight_strings: true,
+            line_comment: Some(String::from("#")),
+            ..Self::default()
+        }
+    }
+
+    fn markdown() -> Self {
+        Self {
+            name: String::from("Markdown"),
+            highlight_markdown: true,
+            ..Self::default()
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn meta(&self) -> Option<MetaStyle> {
+        self.meta
+    }
+
+    /// Returns whether the syntax has nothing to highlight, so rows can skip
+    /// building a highlighting vector altogether.
+    pub fn is_plain(&self) -> bool {
+        self.meta.is_none()
+            && !self.highlight_numbers
+            && self.line_comment.is_none()
+            && !self.highlight_urls
+            && !self.line_continuation
+            && !self.highlight_strings
+            && !self.highlight_markdown
+    }
+
+    pub fn highlight_numbers(&self) -> bool {
+        self.highlight_numbers
+    }
+
+    pub fn line_comment(&self) -> Option<&str> {
+        self.line_comment.as_deref()
+    }
+
+    /// Words highlighted as `Type::Todo` when they appear in a comment.
+    pub fn todo_keywords(&self) -> &[String] {
+        &self.todo_keywords
+    }
+
+    pub fn set_todo_keywords(&mut self, keywords: Vec<String>) {
+        self.todo_keywords = keywords;
+    }
+
+    pub fn highlight_urls(&self) -> bool {
+        self.highlight_urls
+    }
+
+    /// Enables marking `http://` and `https://` links as `Type::Url`. This
+    /// works the same for every syntax, plain text included.
+    pub fn set_highlight_urls(&mut self, highlight_urls: bool) {
+        self.highlight_urls = highlight_urls;
+    }
+
+    /// Whether headings, emphasis and inline code are marked the Markdown
+    /// way.
+    pub fn highlight_markdown(&self) -> bool {
+        self.highlight_markdown
+    }
+
+    /// Whether double-quoted strings are marked as `Type::String`.
+    pub fn highlight_strings(&self) -> bool {
+        self.highlight_strings
+    }
+
+    /// Whether a lone backslash ending a row, which continues the line, is
+    /// marked as `Type::Continuation`.
+    pub fn line_continuation(&self) -> bool {
+        self.line_continuation
+    }
+
+    /// `(open, close)` pairs for `Document::type_char`: once the text before
+    /// the cursor ends with `open`, `close` is inserted after the cursor.
+    pub fn auto_pairs(&self) -> &[(String, String)] {
+        &self.auto_pairs
+    }
+
+    pub fn set_auto_pairs(&mut self, pairs: Vec<(String, String)>) {
+        self.auto_pairs = pairs;
+    }
+}
+
+fn block_comment_pairs() -> Vec<(String, String)> {
+    vec![(String::from("/*"), String::from(" */"))]
+}
+
+/// Finds `ft=`, `filetype=` or `syntax=` after a `vim:` or `vi:` marker.
+fn vim_filetype(line: &str) -> Option<&str> {
+    let (_, options) = ["vim:", "vi:"]
+        .iter()
+        .find_map(|marker| {
+            line.match_indices(marker).find(|(index, _)| {
+                line[..*index]
+                    .chars()
+                    .next_back()
+                    .is_none_or(char::is_whitespace)
+            })
+        })
+        .map(|(index, marker)| line.split_at(index + marker.len()))?;
+    options
+        .split(|c: char| c.is_whitespace() || c == ':')
+        .find_map(|option| {
+            let (name, value) = option.split_once('=')?;
+            matches!(name, "ft" | "filetype" | "syntax").then_some(value)
+        })
+}
+
+/// Finds the mode between `-*-` markers, either bare (`-*- python -*-`) or
+/// as a `mode:` variable.
+fn emacs_mode(line: &str) -> Option<&str> {
+    let (_, rest) = line.split_once("-*-")?;
+    let (variables, _) = rest.split_once("-*-")?;
+    if !variables.contains(':') {
+        return Some(variables.trim());
+    }
+    variables.split(';').find_map(|variable| {
+        let (name, value) = variable.split_once(':')?;
+        name.trim()
+            .eq_ignore_ascii_case("mode")
+            .then(|| value.trim())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn modelines_name_a_language() {
+        let name = |line| Syntax::for_modeline(line).map(|syntax| syntax.name().to_string());
+        assert_eq!(name("// vim: set ft=rust:").as_deref(), Some("Rust"));
+        assert_eq!(name("/* vim:ft=c */").as_deref(), Some("C"));
+        assert_eq!(name("# vi: filetype=python").as_deref(), Some("Python"));
+        assert_eq!(name("# -*- mode: python -*-").as_deref(), Some("Python"));
+        assert_eq!(name("# -*- sh -*-").as_deref(), Some("Shell"));
+        assert_eq!(name("# vim ft=python"), None);
+        assert_eq!(name("savim: ft=python"), None);
+        assert_eq!(name("// vim: ft=cobol"), None);
+    }
+}
@@ -0,0 +1,56 @@
+//! Counts heap allocations made by `Row::find`, which should search the
+//! borrowed row without building substrings, however long the row is.
+
+use ferryte::{Row, SearchDirection};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    // Only count on the thread under test, not the test harness.
+    static COUNTING: Cell<bool> = const { Cell::new(false) };
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if COUNTING.try_with(Cell::get).unwrap_or(false) {
+            ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        }
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+fn allocations<T>(f: impl FnOnce() -> T) -> (T, usize) {
+    ALLOCATIONS.store(0, Ordering::Relaxed);
+    COUNTING.with(|counting| counting.set(true));
+    let result = f();
+    COUNTING.with(|counting| counting.set(false));
+    (result, ALLOCATIONS.load(Ordering::Relaxed))
+}
+
+#[test]
+fn find_on_a_long_line_does_not_allocate() {
+    let line = format!("needle {} e\u{301} 日本 needle", "x".repeat(100_000));
+    let row = Row::from(&line[..]);
+    let end = row.len();
+    let (found, count) = allocations(|| row.find("needle", end, SearchDirection::Backward));
+    assert_eq!(found, Some(end - 6));
+    assert_eq!(count, 0);
+    let (found, count) = allocations(|| row.find("needle", end - 7, SearchDirection::Backward));
+    assert_eq!(found, Some(0));
+    assert_eq!(count, 0);
+    let (found, count) = allocations(|| row.find("needle", 1, SearchDirection::Forward));
+    assert_eq!(found, Some(end - 6));
+    assert_eq!(count, 0);
+}